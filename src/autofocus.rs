@@ -0,0 +1,104 @@
+//! Measuring how far away whatever's under the centre of the screen is, for lenses that focus
+//! themselves.
+//!
+//! The lighting pass writes each pixel's distance along its ray into the alpha channel of the
+//! accumulation target. After a frame seen through the centre of the lens, the pixel at the centre
+//! of the view is copied back from the GPU, which takes a frame or two to arrive.
+
+use std::sync::mpsc::Receiver;
+
+/// Copies are made a whole row at a time, even of a single texel
+const ROW_SIZE: u32 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+pub struct FocusProbe {
+    buffer: wgpu::Buffer,
+    /// Set while a measurement is on its way back
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl FocusProbe {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("focus_probe_buffer"),
+            size: ROW_SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            mapped: None,
+        }
+    }
+
+    /// Copies the texel at `origin` of `texture`, the `Rgba32Float` accumulation target, after
+    /// the work already submitted to `queue`. Does nothing while the last measurement is still on
+    /// its way.
+    pub fn start(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        origin: wgpu::Origin3d,
+    ) {
+        if self.mapped.is_some() {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Focus Probe Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(ROW_SIZE),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, mapped) = std::sync::mpsc::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.mapped = Some(mapped);
+    }
+
+    /// Returns the distance measured if it has arrived, without waiting for it. Nothing was hit
+    /// if it's beyond the raymarcher's reach.
+    pub fn try_finish(&mut self) -> Option<f32> {
+        let result = self.mapped.as_ref()?.try_recv().ok()?;
+        self.mapped = None;
+        if let Err(e) = result {
+            eprintln!("Couldn't measure the focus distance: {:?}", e);
+            return None;
+        }
+        let distance = {
+            let data = self.buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, f32>(&data)[3]
+        };
+        self.buffer.unmap();
+        Some(distance)
+    }
+
+    /// Waits for the measurement on its way back, if there is one
+    pub fn wait(&mut self, device: &wgpu::Device) -> Option<f32> {
+        self.mapped.as_ref()?;
+        device.poll(wgpu::Maintain::Wait);
+        self.try_finish()
+    }
+}
@@ -0,0 +1,468 @@
+//! Bind group layouts shared by the pipelines, kept apart from `State` so the tests can check
+//! them against the bindings each shader declares.
+
+use crate::shaders::{self, ShaderFile};
+
+/// G-buffer textures and the skybox read by the lighting pass
+pub const FULLSCREEN: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        // Albedo texture
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Albedo sampler
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Depth texture
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Depth sampler
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Position texture
+        binding: 4,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Position sampler
+        binding: 5,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Normal texture
+        binding: 6,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Normal sampler
+        binding: 7,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Last frame texture
+        binding: 8,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Last frame sampler
+        binding: 9,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Skybox texture
+        binding: 10,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Skybox sampler
+        binding: 11,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Specular texture
+        binding: 12,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Specular sampler
+        binding: 13,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Emissive texture
+        binding: 14,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Emissive sampler
+        binding: 15,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+];
+
+/// `CameraUniform`
+pub const CAMERA: &[wgpu::BindGroupLayoutEntry] = &[wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+/// Frame count
+pub const UTILS: &[wgpu::BindGroupLayoutEntry] = &[wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+/// Lights and SDF objects
+pub const SCENE: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+/// Material textures and factors
+pub const TEXTURE: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        // Diffuse texture
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Diffuse sampler
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Normal map
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Normal map sampler
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Specular map
+        binding: 4,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Specular map sampler
+        binding: 5,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Emissive map
+        binding: 6,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Emissive map sampler
+        binding: 7,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        // Material factors
+        binding: 8,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+/// Panorama and the cube face being rendered to
+pub const SKYBOX: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        count: None,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        visibility: wgpu::ShaderStages::FRAGMENT,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        count: None,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        visibility: wgpu::ShaderStages::FRAGMENT,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        count: None,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        visibility: wgpu::ShaderStages::FRAGMENT,
+    },
+];
+
+/// Names for the layouts above, so pipelines can list their groups
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Group {
+    Fullscreen,
+    Camera,
+    Utils,
+    Scene,
+    Texture,
+    Skybox,
+}
+
+impl Group {
+    pub fn entries(self) -> &'static [wgpu::BindGroupLayoutEntry] {
+        match self {
+            Group::Fullscreen => FULLSCREEN,
+            Group::Camera => CAMERA,
+            Group::Utils => UTILS,
+            Group::Scene => SCENE,
+            Group::Texture => TEXTURE,
+            Group::Skybox => SKYBOX,
+        }
+    }
+}
+
+/// The groups of the pipeline built from `shader`, in group order. `State::new` makes its
+/// pipeline layouts from this, so the tests check the layouts that are actually used.
+pub fn pipeline_layout(shader: ShaderFile) -> &'static [Group] {
+    match shader {
+        shaders::FULLSCREEN => &[Group::Fullscreen, Group::Camera, Group::Utils, Group::Scene],
+        shaders::GBUFFER => &[Group::Texture, Group::Camera],
+        shaders::SKYBOX => &[Group::Skybox],
+        shaders::ERROR_OVERLAY => &[],
+        _ => panic!("{} isn't a pipeline's shader", shader.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a layout entry of type `entry` can bind a global of type `ty` in `space`
+    fn matches(ty: &naga::TypeInner, space: naga::AddressSpace, entry: &wgpu::BindingType) -> bool {
+        use naga::{ImageClass, ImageDimension, ScalarKind};
+        use wgpu::{BindingType, BufferBindingType, TextureSampleType, TextureViewDimension};
+
+        match (ty, entry) {
+            (_, BindingType::Buffer { ty, .. }) => match (space, ty) {
+                (naga::AddressSpace::Uniform, BufferBindingType::Uniform) => true,
+                (
+                    naga::AddressSpace::Storage { access },
+                    BufferBindingType::Storage { read_only },
+                ) => *read_only != access.contains(naga::StorageAccess::STORE),
+                _ => false,
+            },
+            (naga::TypeInner::Sampler { comparison }, BindingType::Sampler(sampler)) => {
+                *comparison == (*sampler == wgpu::SamplerBindingType::Comparison)
+            }
+            (
+                naga::TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+                BindingType::Texture {
+                    sample_type,
+                    view_dimension,
+                    multisampled,
+                },
+            ) => {
+                let dimension = match (dim, arrayed) {
+                    (ImageDimension::D1, false) => TextureViewDimension::D1,
+                    (ImageDimension::D2, false) => TextureViewDimension::D2,
+                    (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+                    (ImageDimension::D3, false) => TextureViewDimension::D3,
+                    (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+                    (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+                    _ => return false,
+                };
+                let (sample_types_match, multi) = match (class, sample_type) {
+                    (ImageClass::Sampled { kind, multi }, _) => (
+                        matches!(
+                            (kind, sample_type),
+                            (ScalarKind::Float, TextureSampleType::Float { .. })
+                                | (ScalarKind::Sint, TextureSampleType::Sint)
+                                | (ScalarKind::Uint, TextureSampleType::Uint)
+                        ),
+                        *multi,
+                    ),
+                    (ImageClass::Depth { multi }, TextureSampleType::Depth) => (true, *multi),
+                    _ => return false,
+                };
+                dimension == *view_dimension && sample_types_match && multi == *multisampled
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn layouts_match_shader_bindings() {
+        for &shader in shaders::SHADERS.iter().chain([&shaders::ERROR_OVERLAY]) {
+            let groups: Vec<_> = pipeline_layout(shader)
+                .iter()
+                .map(|group| group.entries())
+                .collect();
+            let (module, info) = shaders::validate(shader);
+            for (handle, global) in module.global_variables.iter() {
+                let binding = match &global.binding {
+                    Some(binding) => binding,
+                    None => continue,
+                };
+                let name = global.name.as_deref().unwrap_or("?");
+                let entry = groups
+                    .get(binding.group as usize)
+                    .and_then(|group| group.iter().find(|entry| entry.binding == binding.binding))
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "{} in {} is at group {} binding {}, which isn't in the layout",
+                            name, shader.name, binding.group, binding.binding
+                        )
+                    });
+
+                assert!(
+                    matches(&module.types[global.ty].inner, global.space, &entry.ty),
+                    "{} in {} doesn't match its layout entry {:?}",
+                    name,
+                    shader.name,
+                    entry.ty
+                );
+
+                for (index, entry_point) in module.entry_points.iter().enumerate() {
+                    let stage = match entry_point.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                    if !info.get_entry_point(index)[handle].is_empty() {
+                        assert!(
+                            entry.visibility.contains(stage),
+                            "{} in {} is used by {} but its layout entry isn't visible to {:?}",
+                            name,
+                            shader.name,
+                            entry_point.name,
+                            stage
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
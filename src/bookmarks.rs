@@ -0,0 +1,143 @@
+//! Camera viewpoints to come back to, stored under the number keys.
+//!
+//! Bookmarks are kept beside the scene, so `scene.ron`'s are in `scene.bookmarks.ron`, and the
+//! file is written every time one is stored:
+//!
+//! ```ron
+//! {
+//!     1: (position: (-6.0, 1.0, 0.0), yaw: 0.0, pitch: 0.0, fov: 45.0),
+//!     2: (position: (0.0, 8.0, 0.1), yaw: 90.0, pitch: -89.0, fov: 60.0),
+//! }
+//! ```
+//!
+//! Angles are in degrees, and `fov` is the vertical field of view.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use cgmath::{Deg, Point3};
+
+use crate::camera_path::CameraPose;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Bookmark {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+}
+
+impl Bookmark {
+    /// Bookmarks don't keep roll, since only the flight controller uses it
+    pub fn from_pose(pose: CameraPose) -> Self {
+        Self {
+            position: pose.position.into(),
+            yaw: pose.yaw.0,
+            pitch: pose.pitch.0,
+            fov: pose.fov.0,
+        }
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: Point3::from(self.position),
+            yaw: Deg(self.yaw),
+            pitch: Deg(self.pitch),
+            roll: Deg(0.0),
+            fov: Deg(self.fov),
+        }
+    }
+}
+
+/// A scene's bookmarks, along with the file they're saved to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: BTreeMap<u32, Bookmark>,
+}
+
+impl Bookmarks {
+    /// Loads the bookmarks saved beside the scene at `scene_path`, or none if there aren't any
+    pub fn load<P: AsRef<Path>>(scene_path: P) -> anyhow::Result<Self> {
+        let path = bookmarks_path(scene_path.as_ref());
+        let slots = match path.exists() {
+            true => {
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Couldn't read bookmarks {:?}", path))?;
+                ron::from_str(&text)
+                    .with_context(|| format!("Couldn't parse bookmarks {:?}", path))?
+            }
+            false => BTreeMap::new(),
+        };
+        Ok(Self { path, slots })
+    }
+
+    /// Like `load`, but logs why the file couldn't be loaded and starts with no bookmarks, so a
+    /// bad bookmarks file doesn't stop the scene from loading
+    pub fn load_or_empty<P: AsRef<Path>>(scene_path: P) -> Self {
+        Self::load(&scene_path).unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            Self {
+                path: bookmarks_path(scene_path.as_ref()),
+                slots: BTreeMap::new(),
+            }
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&Bookmark> {
+        self.slots.get(&slot)
+    }
+
+    /// Stores `bookmark` in `slot`, replacing whatever was there, and saves the file
+    pub fn store(&mut self, slot: u32, bookmark: Bookmark) -> anyhow::Result<()> {
+        self.slots.insert(slot, bookmark);
+        let config = ron::ser::PrettyConfig::new().indentor("    ".to_string());
+        let mut text = ron::ser::to_string_pretty(&self.slots, config)?;
+        text.push('\n');
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Couldn't save bookmarks to {:?}", self.path))
+    }
+}
+
+fn bookmarks_path(scene_path: &Path) -> PathBuf {
+    scene_path.with_extension("bookmarks.ron")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bookmarks_are_saved_beside_the_scene() {
+        let directory = std::env::temp_dir().join(format!("flashbang-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let scene_path = directory.join("scene.ron");
+
+        let mut bookmarks = Bookmarks::load(&scene_path).unwrap();
+        assert_eq!(bookmarks.get(1), None);
+        let bookmark = Bookmark {
+            position: [1.0, 2.0, 3.0],
+            yaw: -90.0,
+            pitch: 10.0,
+            fov: 60.0,
+        };
+        bookmarks.store(1, bookmark.clone()).unwrap();
+        assert_eq!(bookmarks.path(), directory.join("scene.bookmarks.ron"));
+
+        let loaded = Bookmarks::load(&scene_path).unwrap();
+        assert_eq!(loaded.get(1), Some(&bookmark));
+        assert_eq!(Bookmark::from_pose(bookmark.pose()), bookmark);
+
+        std::fs::write(bookmarks.path(), "{1: (position: (1.0,").unwrap();
+        assert!(Bookmarks::load(&scene_path).is_err());
+        let empty = Bookmarks::load_or_empty(&scene_path);
+        assert_eq!(empty.get(1), None);
+        assert_eq!(empty.path(), bookmarks.path());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,7 +1,4 @@
 use cgmath::*;
-use std::f32::consts::FRAC_PI_2;
-use std::time::Duration;
-use winit::event::*;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -11,11 +8,10 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    pub view_proj: [[f32; 4]; 4],
     pub pos: [f32; 3],
     _padding: u32,
     pub dir: [f32; 3],
@@ -24,11 +20,26 @@ pub struct CameraUniform {
     _padding3: u32,
     pub up: [f32; 3],
     pub aspect: f32,
+    /// `ProjectionMode` as a number, in declaration order
+    pub projection: u32,
+    /// Scales screen coordinates to rays: the tangent of half the vertical field of view in
+    /// perspective, half the view's height in orthographic and half the image circle's angle in
+    /// fisheye
+    pub view_scale: f32,
+    _padding4: [u32; 2],
+    /// From `pos` to the eye this view is seen from, for stereo
+    pub eye_offset: [f32; 3],
+    /// Distance at which the eye sees everything where `pos` would
+    pub convergence: f32,
+    /// From the eye to the point on the lens this frame sees through
+    pub lens_offset: [f32; 3],
+    pub focus_distance: f32,
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
+            view_proj: cgmath::Matrix4::identity().into(),
             pos: [0.0; 3],
             _padding: 0,
             dir: [0.0; 3],
@@ -37,25 +48,84 @@ impl CameraUniform {
             _padding3: 0,
             up: [0.0, 1.0, 0.0],
             aspect: 1.0,
+            projection: 0,
+            view_scale: 1.0,
+            _padding4: [0; 2],
+            eye_offset: [0.0; 3],
+            convergence: 1.0,
+            lens_offset: [0.0; 3],
+            focus_distance: 1.0,
         }
     }
 
-    pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
+    /// Sets up the view from `eye`, through the point `lens` on the lens. Each is an offset from
+    /// the one before, so the lens is relative to the eye.
+    pub fn update_view_proj(
+        &mut self,
+        camera: &Camera,
+        projection: &Projection,
+        eye: ViewOffset,
+        lens: ViewOffset,
+    ) {
+        self.view_proj =
+            (projection.calc_matrix() * lens.matrix() * eye.matrix() * camera.calc_matrix()).into();
+        self.eye_offset = eye.world_offset(camera).into();
+        self.convergence = eye.distance;
+        self.lens_offset = lens.world_offset(camera).into();
+        self.focus_distance = lens.distance;
         self.pos = camera.position.into();
-        let direction = camera.direction();
-        let right = direction.cross(Vector3::new(0.0, 1.0, 0.0)).normalize();
-        self.dir = direction.into();
-        self.right = right.into();
-        self.up = direction.cross(right).normalize().into();
+        self.dir = camera.direction().into();
+        self.right = camera.right().into();
+        self.up = camera.up().into();
         self.aspect = projection.aspect;
+        self.projection = projection.mode as u32;
+        self.view_scale = projection.view_scale();
     }
 }
 
-#[derive(Debug)]
+/// A viewpoint moved across from the camera, which still sees everything `distance` in front of
+/// it where the camera does. Stereo eyes converging on the screen and points on a lens focusing on
+/// a plane are both views like this.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewOffset {
+    /// Along the camera's right and up directions
+    pub offset: Vector2<f32>,
+    pub distance: f32,
+}
+
+impl ViewOffset {
+    /// The camera's own view
+    pub fn centre(distance: f32) -> Self {
+        Self {
+            offset: Vector2::zero(),
+            distance,
+        }
+    }
+
+    /// In view space, the viewpoint moves across by the offset, and everything moves back across
+    /// by the offset's fraction of each unit of depth to `distance`. The view is sheared rather
+    /// than turned, so everything at that distance stays where the camera would see it.
+    fn matrix(&self) -> Matrix4<f32> {
+        let mut matrix = Matrix4::from_translation(-self.offset.extend(0.0));
+        matrix.z.x = -self.offset.x / self.distance;
+        matrix.z.y = -self.offset.y / self.distance;
+        matrix
+    }
+
+    fn world_offset(&self, camera: &Camera) -> Vector3<f32> {
+        camera.right() * self.offset.x + camera.up() * self.offset.y
+    }
+}
+
+/// A camera's pose. It looks along the direction given by `yaw` around the Y axis and `pitch`
+/// above the horizon, then rolls by `roll` around that direction, tilting its up vector towards
+/// its right.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
-    yaw: Rad<f32>,
-    pitch: Rad<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    pub roll: Rad<f32>,
 }
 
 impl Camera {
@@ -68,24 +138,244 @@ impl Camera {
             position: position.into(),
             yaw: yaw.into(),
             pitch: pitch.into(),
+            roll: Rad(0.0),
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
-        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
+    /// A camera at `position` facing along `direction`, which doesn't need to be normalised
+    pub fn looking_along<V: Into<Point3<f32>>>(position: V, direction: Vector3<f32>) -> Self {
+        let direction = direction.normalize();
+        Self::new(
+            position,
+            Rad(direction.z.atan2(direction.x)),
+            Rad(direction.y.asin()),
+        )
+    }
+
+    /// A camera at `position` rotated by `rotation`, which takes +X onto its direction and +Y
+    /// onto its up vector, as `rotation()` does
+    pub fn from_rotation<V: Into<Point3<f32>>>(position: V, rotation: Quaternion<f32>) -> Self {
+        Self::from_axes(
+            position,
+            rotation * Vector3::unit_x(),
+            rotation * Vector3::unit_y(),
         )
     }
 
+    /// A camera at `position` looking along `forward`, rolled so that its up vector is `up`
+    pub fn from_axes<V: Into<Point3<f32>>>(
+        position: V,
+        forward: Vector3<f32>,
+        up: Vector3<f32>,
+    ) -> Self {
+        let forward = forward.normalize();
+        let mut camera = Self::looking_along(position, forward);
+        if forward.x.hypot(forward.z) < 1e-6 {
+            // Looking straight up or down, any yaw will do, so take the one leaving no roll
+            let right = forward.cross(up);
+            camera.yaw = Rad((-right.x).atan2(right.z));
+        }
+        let (right, level_up) = camera.level_axes();
+        camera.roll = Rad(up.dot(right).atan2(up.dot(level_up)));
+        camera
+    }
+
+    /// Right and up vectors before rolling
+    fn level_axes(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        let right = Vector3::new(-sin_yaw, 0.0, cos_yaw);
+        (right, right.cross(self.direction()))
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.direction(), self.up())
+    }
+
     pub fn direction(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
         Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
     }
+
+    pub fn right(&self) -> Vector3<f32> {
+        let (right, up) = self.level_axes();
+        let (sin_roll, cos_roll) = self.roll.0.sin_cos();
+        right * cos_roll - up * sin_roll
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        let (right, up) = self.level_axes();
+        let (sin_roll, cos_roll) = self.roll.0.sin_cos();
+        up * cos_roll + right * sin_roll
+    }
+
+    /// Rotation taking +X onto `direction()` and +Y onto `up()`
+    pub fn rotation(&self) -> Quaternion<f32> {
+        Quaternion::from_angle_y(-self.yaw)
+            * Quaternion::from_angle_z(self.pitch)
+            * Quaternion::from_angle_x(self.roll)
+    }
+}
+
+/// A thin lens, which blurs whatever's away from its focus distance. Each frame looks through a
+/// different point on the aperture, and the frames average out into the blur. Only the point at
+/// the aperture's centre is used while anything's moving, so the view stays sharp until it stops.
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Lens {
+    /// Diameter of the aperture in world units. Zero is a pinhole, with everything in focus.
+    pub aperture: f32,
+    /// Distance to the plane in focus. Without one the lens focuses on whatever's under the
+    /// centre of the screen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+    /// Straight edges around the aperture, which shape out of focus highlights into polygons.
+    /// Fewer than three is a round aperture.
+    pub blades: u32,
+}
+
+impl Lens {
+    /// Point `index` of a sequence spread evenly over the aperture, relative to its centre. The
+    /// first point is the centre itself.
+    pub fn sample(&self, index: u32) -> Vector2<f32> {
+        if index == 0 {
+            return Vector2::zero();
+        }
+        let u = radical_inverse(index, 2);
+        let v = radical_inverse(index, 3);
+        let radius = self.aperture / 2.0;
+        if self.blades < 3 {
+            // Square root to spread the points evenly over the area rather than the radius
+            let (sin, cos) = (u * 2.0 * std::f32::consts::PI).sin_cos();
+            return Vector2::new(cos, sin) * v.sqrt() * radius;
+        }
+
+        // A triangle of the polygon, picked by `u`, with what's left of `u` and `v` placing the
+        // point inside it
+        let blades = self.blades as f32;
+        let blade = (u * blades).floor();
+        let (a, b) = ((u * blades).fract(), v);
+        let (a, b) = if a + b > 1.0 {
+            (1.0 - a, 1.0 - b)
+        } else {
+            (a, b)
+        };
+        let corner = |i: f32| {
+            let (sin, cos) = (i / blades * 2.0 * std::f32::consts::PI).sin_cos();
+            Vector2::new(cos, sin)
+        };
+        (corner(blade) * a + corner(blade + 1.0) * b) * radius
+    }
+}
+
+/// Van der Corput sequence in `base`, mirroring the digits of `index` around the point
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut scale = 1.0 / base as f32;
+    while index > 0 {
+        result += (index % base) as f32 * scale;
+        index /= base;
+        scale /= base as f32;
+    }
+    result
+}
+
+/// How the two eyes of a stereo view share the frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum StereoMode {
+    /// A single view from the camera's position
+    #[default]
+    Off,
+    /// The left eye's view on the left half of the frame and the right eye's on the right
+    SideBySide,
+    /// The left eye's view in red and the right eye's in green and blue, for red/cyan glasses
+    Anaglyph,
+}
+
+impl StereoMode {
+    pub const ALL: [StereoMode; 3] = [
+        StereoMode::Off,
+        StereoMode::SideBySide,
+        StereoMode::Anaglyph,
+    ];
+}
+
+/// Two eyes either side of the camera, spaced along its right direction
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Stereo {
+    pub mode: StereoMode,
+    /// Distance between the eyes in world units
+    pub interocular: f32,
+    /// Distance at which the eyes' views meet, which looks to be at the depth of the screen.
+    /// Anything nearer stands out in front of it.
+    pub convergence: f32,
+}
+
+impl Stereo {
+    /// The eyes to render, left first, or just the camera's own view while stereo is off
+    pub fn eyes(&self) -> Vec<ViewOffset> {
+        if self.mode == StereoMode::Off {
+            return vec![ViewOffset::centre(self.convergence)];
+        }
+        [-0.5, 0.5]
+            .into_iter()
+            .map(|side| ViewOffset {
+                offset: Vector2::new(side * self.interocular, 0.0),
+                distance: self.convergence,
+            })
+            .collect()
+    }
+
+    /// Width of each eye's view in a frame `width` wide
+    pub fn eye_width(&self, width: u32) -> u32 {
+        match self.mode {
+            StereoMode::SideBySide => (width / 2).max(1),
+            _ => width,
+        }
+    }
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            mode: StereoMode::Off,
+            interocular: 0.065,
+            convergence: 5.0,
+        }
+    }
+}
+
+/// How the view is mapped onto the screen. Meshes are only drawn in the modes a matrix can
+/// express, perspective and orthographic, so fisheye and equirectangular views show just the
+/// raymarched objects and the sky.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ProjectionMode {
+    #[default]
+    Perspective,
+    /// Parallel rays, `ortho_height` units tall
+    Orthographic,
+    /// Equidistant fisheye, with a circular image `fisheye_fov` across that fills the height
+    Fisheye,
+    /// A full 360° by 180° panorama, which is only undistorted at an aspect ratio of 2:1
+    Equirectangular,
+}
+
+impl ProjectionMode {
+    pub const ALL: [ProjectionMode; 4] = [
+        ProjectionMode::Perspective,
+        ProjectionMode::Orthographic,
+        ProjectionMode::Fisheye,
+        ProjectionMode::Equirectangular,
+    ];
+
+    /// Whether the mode is a projection matrix, so meshes can be rasterised with it
+    pub fn is_linear(self) -> bool {
+        matches!(
+            self,
+            ProjectionMode::Perspective | ProjectionMode::Orthographic
+        )
+    }
 }
 
 pub struct Projection {
@@ -93,6 +383,9 @@ pub struct Projection {
     fovy: Rad<f32>,
     znear: f32,
     zfar: f32,
+    pub mode: ProjectionMode,
+    pub ortho_height: f32,
+    pub fisheye_fov: Rad<f32>,
 }
 
 impl Projection {
@@ -102,6 +395,9 @@ impl Projection {
             fovy: fovy.into(),
             znear,
             zfar,
+            mode: ProjectionMode::Perspective,
+            ortho_height: 10.0,
+            fisheye_fov: Rad(std::f32::consts::PI),
         }
     }
 
@@ -109,105 +405,120 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
     }
-}
 
-#[derive(Debug)]
-pub struct CameraController {
-    amount_left: f32,
-    amount_right: f32,
-    amount_forward: f32,
-    amount_backward: f32,
-    amount_up: f32,
-    amount_down: f32,
-    rotate_horizontal: f32,
-    rotate_vertical: f32,
-    speed: f32,
-    sensitivity: f32,
-}
+    pub fn set_fovy<F: Into<Rad<f32>>>(&mut self, fovy: F) {
+        self.fovy = fovy.into();
+    }
 
-impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
-        Self {
-            amount_left: 0.0,
-            amount_right: 0.0,
-            amount_forward: 0.0,
-            amount_backward: 0.0,
-            amount_up: 0.0,
-            amount_down: 0.0,
-            rotate_horizontal: 0.0,
-            rotate_vertical: 0.0,
-            speed,
-            sensitivity,
-        }
+    pub fn znear(&self) -> f32 {
+        self.znear
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
-        let amount = if state == ElementState::Pressed {
-            1.0
-        } else {
-            0.0
-        };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.amount_forward = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.amount_backward = amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.amount_left = amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.amount_right = amount;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.amount_down = amount;
-                true
+    /// The matrix meshes are drawn with. Modes that aren't linear fall back to perspective, which
+    /// doesn't matter since nothing is drawn with it.
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        let projection = match self.mode {
+            ProjectionMode::Orthographic => {
+                let top = self.ortho_height / 2.0;
+                let right = top * self.aspect;
+                ortho(-right, right, -top, top, self.znear, self.zfar)
             }
-            _ => false,
-        }
+            _ => perspective(self.fovy, self.aspect, self.znear, self.zfar),
+        };
+        OPENGL_TO_WGPU_MATRIX * projection
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal += mouse_dx as f32;
-        self.rotate_vertical += mouse_dy as f32;
+    fn view_scale(&self) -> f32 {
+        match self.mode {
+            ProjectionMode::Perspective => (self.fovy / 2.0).tan(),
+            ProjectionMode::Orthographic => self.ortho_height / 2.0,
+            ProjectionMode::Fisheye => self.fisheye_fov.0 / 2.0,
+            ProjectionMode::Equirectangular => 1.0,
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
-        let dt = dt.as_secs_f32();
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
 
-        // Move forward/backward and left/right
-        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
-        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
-        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+    #[test]
+    fn lens_samples_stay_inside_the_aperture() {
+        for blades in [0, 6] {
+            let lens = Lens {
+                aperture: 2.0,
+                focus_distance: None,
+                blades,
+            };
+            assert_eq!(lens.sample(0), Vector2::zero());
+            let samples: Vec<_> = (1..256).map(|i| lens.sample(i)).collect();
+            assert!(samples
+                .iter()
+                .all(|sample| sample.magnitude() <= 1.0 + 1e-5));
+            // Spread over the whole aperture, not bunched in the middle
+            let mean = samples.iter().fold(Vector2::zero(), |sum, s| sum + s) / 255.0;
+            assert!(mean.magnitude() < 0.05);
+            assert!(samples.iter().any(|sample| sample.magnitude() > 0.9));
+        }
+    }
 
-        // Move up/down
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+    #[test]
+    fn stereo_eyes_agree_at_the_convergence_distance() {
+        let stereo = Stereo {
+            mode: StereoMode::Anaglyph,
+            interocular: 0.5,
+            convergence: 4.0,
+        };
+        let camera = Camera::new((1.0, 2.0, 3.0), Deg(30.0), Deg(-10.0));
+        let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let screen = |eye: ViewOffset, point: Point3<f32>| {
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(&camera, &projection, eye, ViewOffset::centre(1.0));
+            let clip = Matrix4::from(uniform.view_proj) * point.to_homogeneous();
+            Vector2::new(clip.x, clip.y) / clip.w
+        };
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity;
+        let [left, right] = stereo.eyes()[..] else {
+            panic!("Expected two eyes");
+        };
+        let across = camera.right() * 0.3 + camera.up() * 0.2;
+        let converged = camera.position + camera.direction() * 4.0 + across;
+        let centre = screen(ViewOffset::centre(4.0), converged);
+        assert!((screen(left, converged) - centre).magnitude() < 1e-5);
+        assert!((screen(right, converged) - centre).magnitude() < 1e-5);
+        // Anything further away is further right for the right eye
+        let beyond = camera.position + camera.direction() * 8.0 + across;
+        assert!(screen(right, beyond).x > screen(left, beyond).x);
+    }
 
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
+    #[test]
+    fn rotations_round_trip() {
+        for (yaw, pitch, roll) in [
+            (0.0, 0.0, 0.0),
+            (30.0, 20.0, 0.0),
+            (-120.0, -45.0, 60.0),
+            (200.0, 80.0, -150.0),
+            (45.0, 90.0, 30.0),
+        ] {
+            let mut camera = Camera::new((1.0, 2.0, 3.0), Deg(yaw), Deg(pitch));
+            camera.roll = Deg(roll).into();
+            let rotation = camera.rotation();
+            assert_close(rotation * Vector3::unit_x(), camera.direction());
+            assert_close(rotation * Vector3::unit_y(), camera.up());
+            assert_close(rotation * Vector3::unit_z(), camera.right());
 
-        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = -Rad(SAFE_FRAC_PI_2);
-        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = Rad(SAFE_FRAC_PI_2);
+            // Looking straight up, yaw and roll trade off against each other, but the pose is the
+            // same
+            let rebuilt = Camera::from_rotation(camera.position, rotation);
+            assert_close(rebuilt.direction(), camera.direction());
+            assert_close(rebuilt.up(), camera.up());
         }
     }
 }
@@ -0,0 +1,508 @@
+use cgmath::*;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::time::Duration;
+use winit::event::*;
+
+use crate::camera::Camera;
+use crate::input::{self, Binding, InputMap};
+
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+/// How quickly the controllers that keep the horizon level take out roll left by the flight
+/// controller, per second
+const LEVELLING_RATE: f32 = 4.0;
+/// Scroll distance one line of a mouse wheel counts as, for touchpads that scroll in pixels
+const PIXELS_PER_LINE: f32 = 20.0;
+/// Each line scrolled multiplies or divides the movement speed by this
+const SCROLL_SPEED_FACTOR: f32 = 1.25;
+/// Movement speeds scrolling can reach, in units per second. The fastest crosses the whole
+/// raymarched distance in a few seconds.
+const MIN_SPEED: f32 = 0.01;
+const MAX_SPEED: f32 = 500.0;
+/// Speed multipliers while sprinting or moving slowly
+const SPRINT_FACTOR: f32 = 5.0;
+const SLOW_FACTOR: f32 = 0.2;
+/// Time constants, in seconds, that velocity and mouse look ease towards where they're heading
+/// with. Larger is smoother and less responsive.
+const MOVEMENT_SMOOTHING: f32 = 0.1;
+const LOOK_SMOOTHING: f32 = 0.03;
+
+/// Turns input into camera movement. Every controller works on the same `Camera`, so switching
+/// between them leaves the camera where it was.
+pub trait CameraController {
+    fn name(&self) -> &'static str;
+
+    /// Called when the controller takes over the camera, so it can carry on from its pose
+    fn activate(&mut self, _camera: &Camera) {}
+
+    /// Adds the actions the controller responds to, with their default bindings
+    fn register_actions(&self, input: &mut InputMap);
+
+    /// An action starting or stopping. Returns whether the controller uses it.
+    fn process_action(&mut self, action: &str, pressed: bool) -> bool;
+
+    /// Raw mouse motion, whether or not any buttons are held
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64);
+
+    fn process_scroll(&mut self, _delta: &MouseScrollDelta) -> bool {
+        false
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration);
+}
+
+/// Moving in six directions, by default with WASD, the arrow keys, Space and left Shift, and
+/// left Control to sprint and left Alt to move slowly. Scrolling changes the speed.
+#[derive(Debug)]
+struct Movement {
+    amount_left: f32,
+    amount_right: f32,
+    amount_forward: f32,
+    amount_backward: f32,
+    amount_up: f32,
+    amount_down: f32,
+    sprint: bool,
+    slow: bool,
+    speed: f32,
+    /// Along the camera's forward, right and up directions, easing towards what the keys ask for
+    velocity: Vector3<f32>,
+}
+
+impl Movement {
+    fn new(speed: f32) -> Self {
+        Self {
+            amount_left: 0.0,
+            amount_right: 0.0,
+            amount_forward: 0.0,
+            amount_backward: 0.0,
+            amount_up: 0.0,
+            amount_down: 0.0,
+            sprint: false,
+            slow: false,
+            speed,
+            velocity: Vector3::zero(),
+        }
+    }
+
+    fn register_actions(input: &mut InputMap) {
+        use VirtualKeyCode::*;
+        input.register(input::MOVE_FORWARD, &[Binding::Key(W), Binding::Key(Up)]);
+        input.register(input::MOVE_BACKWARD, &[Binding::Key(S), Binding::Key(Down)]);
+        input.register(input::MOVE_LEFT, &[Binding::Key(A), Binding::Key(Left)]);
+        input.register(input::MOVE_RIGHT, &[Binding::Key(D), Binding::Key(Right)]);
+        input.register(input::MOVE_UP, &[Binding::Key(Space)]);
+        input.register(input::MOVE_DOWN, &[Binding::Key(LShift)]);
+        input.register(input::SPRINT, &[Binding::Key(LControl)]);
+        input.register(input::SLOW, &[Binding::Key(LAlt)]);
+    }
+
+    fn process_action(&mut self, action: &str, pressed: bool) -> bool {
+        let amount = if pressed { 1.0 } else { 0.0 };
+        match action {
+            input::MOVE_FORWARD => self.amount_forward = amount,
+            input::MOVE_BACKWARD => self.amount_backward = amount,
+            input::MOVE_LEFT => self.amount_left = amount,
+            input::MOVE_RIGHT => self.amount_right = amount,
+            input::MOVE_UP => self.amount_up = amount,
+            input::MOVE_DOWN => self.amount_down = amount,
+            input::SPRINT => self.sprint = pressed,
+            input::SLOW => self.slow = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Scales the speed geometrically, so it's as quick to go from 0.1 to 1 as from 100 to 1000
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) -> bool {
+        self.speed = (self.speed * SCROLL_SPEED_FACTOR.powf(scroll_lines(delta)))
+            .clamp(MIN_SPEED, MAX_SPEED);
+        true
+    }
+
+    /// Eases the velocity towards the one the keys ask for, and returns it
+    fn update(&mut self, dt: f32) -> Vector3<f32> {
+        let mut speed = self.speed;
+        if self.sprint {
+            speed *= SPRINT_FACTOR;
+        }
+        if self.slow {
+            speed *= SLOW_FACTOR;
+        }
+        let target = Vector3::new(
+            self.amount_forward - self.amount_backward,
+            self.amount_right - self.amount_left,
+            self.amount_up - self.amount_down,
+        ) * speed;
+        self.velocity += (target - self.velocity) * smoothing(dt, MOVEMENT_SMOOTHING);
+        // Stops outright in the end, rather than creeping along forever
+        if target == Vector3::zero() && self.velocity.magnitude() < MIN_SPEED * 0.01 {
+            self.velocity = Vector3::zero();
+        }
+        self.velocity
+    }
+}
+
+/// Mouse movement that hasn't been turned into rotation yet
+#[derive(Debug, Default)]
+struct MouseLook {
+    horizontal: f32,
+    vertical: f32,
+}
+
+impl MouseLook {
+    fn add(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.horizontal += mouse_dx as f32;
+        self.vertical += mouse_dy as f32;
+    }
+
+    /// Takes the part of the movement that should be applied over `dt`
+    fn take(&mut self, dt: f32) -> (f32, f32) {
+        let mut fraction = smoothing(dt, LOOK_SMOOTHING);
+        // Finishes off movements smaller than a pixel
+        if self.horizontal.abs() < 1.0 && self.vertical.abs() < 1.0 && dt > 0.0 {
+            fraction = 1.0;
+        }
+        let taken = (self.horizontal * fraction, self.vertical * fraction);
+        self.horizontal -= taken.0;
+        self.vertical -= taken.1;
+        taken
+    }
+}
+
+/// Fraction of the way to its target an exponentially smoothed value covers in `dt`
+fn smoothing(dt: f32, time_constant: f32) -> f32 {
+    1.0 - (-dt / time_constant).exp()
+}
+
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, lines) => *lines,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+    }
+}
+
+/// Free flying with the horizon kept level. WASD moves, Space and Shift rise and sink, and
+/// dragging with the left button looks around.
+#[derive(Debug)]
+pub struct FlyController {
+    movement: Movement,
+    looking: bool,
+    look: MouseLook,
+    sensitivity: f32,
+}
+
+impl FlyController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            movement: Movement::new(speed),
+            looking: false,
+            look: MouseLook::default(),
+            sensitivity,
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn name(&self) -> &'static str {
+        "fly"
+    }
+
+    fn register_actions(&self, input: &mut InputMap) {
+        Movement::register_actions(input);
+        input.register(input::LOOK, &[Binding::Mouse(MouseButton::Left)]);
+    }
+
+    fn process_action(&mut self, action: &str, pressed: bool) -> bool {
+        match action {
+            input::LOOK => self.looking = pressed,
+            _ => return self.movement.process_action(action, pressed),
+        }
+        true
+    }
+
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.looking {
+            self.look.add(mouse_dx, mouse_dy);
+        }
+    }
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) -> bool {
+        self.movement.process_scroll(delta)
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let velocity = self.movement.update(dt);
+
+        // Move forward/backward and left/right
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+        camera.position += forward * velocity.x * dt;
+        camera.position += right * velocity.y * dt;
+
+        // Move up/down
+        camera.position.y += velocity.z * dt;
+
+        // Rotate
+        let (rotate_horizontal, rotate_vertical) = self.look.take(dt);
+        camera.yaw += Rad(rotate_horizontal) * self.sensitivity;
+        camera.pitch += Rad(-rotate_vertical) * self.sensitivity;
+
+        clamp_pitch(camera);
+        level_roll(camera, dt);
+    }
+}
+
+/// Circles a target point. Dragging with the left button orbits, dragging with the middle button
+/// pans and scrolling zooms.
+#[derive(Debug)]
+pub struct OrbitController {
+    target: Point3<f32>,
+    distance: f32,
+    orbiting: bool,
+    panning: bool,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    pan_horizontal: f32,
+    pan_vertical: f32,
+    zoom: f32,
+    sensitivity: f32,
+}
+
+impl OrbitController {
+    /// Starts orbiting a point `distance` in front of the camera
+    pub fn new(distance: f32, sensitivity: f32) -> Self {
+        Self {
+            target: Point3::origin(),
+            distance,
+            orbiting: false,
+            panning: false,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
+            zoom: 0.0,
+            sensitivity,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn name(&self) -> &'static str {
+        "orbit"
+    }
+
+    fn activate(&mut self, camera: &Camera) {
+        self.target = camera.position + camera.direction() * self.distance;
+    }
+
+    fn register_actions(&self, input: &mut InputMap) {
+        input.register(input::ORBIT, &[Binding::Mouse(MouseButton::Left)]);
+        input.register(input::PAN, &[Binding::Mouse(MouseButton::Middle)]);
+    }
+
+    fn process_action(&mut self, action: &str, pressed: bool) -> bool {
+        match action {
+            input::ORBIT => self.orbiting = pressed,
+            input::PAN => self.panning = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.orbiting {
+            self.rotate_horizontal += mouse_dx as f32;
+            self.rotate_vertical += mouse_dy as f32;
+        }
+        if self.panning {
+            self.pan_horizontal += mouse_dx as f32;
+            self.pan_vertical += mouse_dy as f32;
+        }
+    }
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) -> bool {
+        self.zoom += scroll_lines(delta);
+        true
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let idle = self.rotate_horizontal == 0.0
+            && self.rotate_vertical == 0.0
+            && self.pan_horizontal == 0.0
+            && self.pan_vertical == 0.0
+            && self.zoom == 0.0;
+        // Placing the camera again would round its position off slightly, which shouldn't count
+        // as movement
+        if idle && camera.roll == Rad(0.0) {
+            return;
+        }
+
+        // Each line scrolled moves a tenth of the way to the target
+        self.distance = (self.distance * 0.9f32.powf(self.zoom)).max(0.01);
+
+        // The target moves with the cursor, by an amount that looks the same at any distance
+        let pan_scale = self.distance * self.sensitivity;
+        self.target += camera.right() * -self.pan_horizontal * pan_scale;
+        self.target += camera.up() * self.pan_vertical * pan_scale;
+
+        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity;
+        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity;
+        clamp_pitch(camera);
+        level_roll(camera, dt.as_secs_f32());
+        camera.position = self.target - camera.direction() * self.distance;
+
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+        self.zoom = 0.0;
+    }
+}
+
+/// Six degrees of freedom, with every movement relative to the camera rather than the horizon.
+/// WASD, Space and Shift move along the camera's own axes, dragging with the left button turns
+/// and Q and E roll.
+#[derive(Debug)]
+pub struct FlightController {
+    movement: Movement,
+    roll_left: f32,
+    roll_right: f32,
+    looking: bool,
+    look: MouseLook,
+    sensitivity: f32,
+    /// Radians per second
+    roll_speed: f32,
+}
+
+impl FlightController {
+    pub fn new(speed: f32, sensitivity: f32, roll_speed: f32) -> Self {
+        Self {
+            movement: Movement::new(speed),
+            roll_left: 0.0,
+            roll_right: 0.0,
+            looking: false,
+            look: MouseLook::default(),
+            sensitivity,
+            roll_speed,
+        }
+    }
+}
+
+impl CameraController for FlightController {
+    fn name(&self) -> &'static str {
+        "flight"
+    }
+
+    fn register_actions(&self, input: &mut InputMap) {
+        Movement::register_actions(input);
+        input.register(input::LOOK, &[Binding::Mouse(MouseButton::Left)]);
+        input.register(input::ROLL_LEFT, &[Binding::Key(VirtualKeyCode::Q)]);
+        input.register(input::ROLL_RIGHT, &[Binding::Key(VirtualKeyCode::E)]);
+    }
+
+    fn process_action(&mut self, action: &str, pressed: bool) -> bool {
+        let amount = if pressed { 1.0 } else { 0.0 };
+        match action {
+            input::ROLL_LEFT => self.roll_left = amount,
+            input::ROLL_RIGHT => self.roll_right = amount,
+            input::LOOK => self.looking = pressed,
+            _ => return self.movement.process_action(action, pressed),
+        }
+        true
+    }
+
+    fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.looking {
+            self.look.add(mouse_dx, mouse_dy);
+        }
+    }
+
+    fn process_scroll(&mut self, delta: &MouseScrollDelta) -> bool {
+        self.movement.process_scroll(delta)
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let velocity = self.movement.update(dt);
+        let (rotate_horizontal, rotate_vertical) = self.look.take(dt);
+        let roll = (self.roll_right - self.roll_left) * self.roll_speed * dt;
+        // Rebuilding the camera from a rotation rounds its angles off slightly, which shouldn't
+        // count as movement
+        if velocity == Vector3::zero()
+            && rotate_horizontal == 0.0
+            && rotate_vertical == 0.0
+            && roll == 0.0
+        {
+            return;
+        }
+
+        // Turns around the camera's own axes, which are +X forward, +Y up and +Z right
+        let rotation = (camera.rotation()
+            * Quaternion::from_angle_y(Rad(-rotate_horizontal * self.sensitivity))
+            * Quaternion::from_angle_z(Rad(-rotate_vertical * self.sensitivity))
+            * Quaternion::from_angle_x(Rad(roll)))
+        .normalize();
+
+        let movement = rotation * Vector3::unit_x() * velocity.x
+            + rotation * Vector3::unit_z() * velocity.y
+            + rotation * Vector3::unit_y() * velocity.z;
+        *camera = Camera::from_rotation(camera.position + movement * dt, rotation);
+    }
+}
+
+fn clamp_pitch(camera: &mut Camera) {
+    if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
+        camera.pitch = -Rad(SAFE_FRAC_PI_2);
+    } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
+        camera.pitch = Rad(SAFE_FRAC_PI_2);
+    }
+}
+
+/// Eases any roll out, the short way round, so switching from the flight controller doesn't
+/// snap the horizon level
+fn level_roll(camera: &mut Camera, dt: f32) {
+    if camera.roll == Rad(0.0) {
+        return;
+    }
+    let roll = (camera.roll.0 + PI).rem_euclid(2.0 * PI) - PI;
+    let roll = roll * (-LEVELLING_RATE * dt).exp();
+    camera.roll = Rad(if roll.abs() < 1e-4 { 0.0 } else { roll });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling_scales_speed_within_limits() {
+        let mut movement = Movement::new(2.0);
+        movement.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1.0));
+        assert_eq!(movement.speed, 2.0 * SCROLL_SPEED_FACTOR);
+        movement.process_scroll(&MouseScrollDelta::LineDelta(0.0, -1.0));
+        assert!((movement.speed - 2.0).abs() < 1e-5);
+        movement.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1000.0));
+        assert_eq!(movement.speed, MAX_SPEED);
+        movement.process_scroll(&MouseScrollDelta::LineDelta(0.0, -1000.0));
+        assert_eq!(movement.speed, MIN_SPEED);
+    }
+
+    #[test]
+    fn velocity_eases_in_and_comes_to_a_stop() {
+        let mut movement = Movement::new(2.0);
+        movement.process_action(input::MOVE_FORWARD, true);
+        movement.process_action(input::SPRINT, true);
+        let first = movement.update(1.0 / 60.0).x;
+        assert!(first > 0.0 && first < 2.0 * SPRINT_FACTOR);
+        for _ in 0..120 {
+            movement.update(1.0 / 60.0);
+        }
+        assert!((movement.velocity.x - 2.0 * SPRINT_FACTOR).abs() < 1e-3);
+
+        movement.process_action(input::MOVE_FORWARD, false);
+        for _ in 0..120 {
+            movement.update(1.0 / 60.0);
+        }
+        assert_eq!(movement.velocity, Vector3::zero());
+    }
+}
@@ -0,0 +1,358 @@
+//! Keyframed camera animation.
+//!
+//! A track is a list of camera poses at times in seconds, stored in the scene file:
+//!
+//! ```ron
+//! camera_tracks: [
+//!     (
+//!         name: "flythrough",
+//!         // Linear, CatmullRom or Bezier. CatmullRom by default.
+//!         interpolation: Bezier,
+//!         keyframes: [
+//!             (time: 0.0, position: (-6.0, 1.0, 0.0), yaw: 0.0, pitch: 0.0, fov: 45.0),
+//!             // Easing applies to the segment leading on to the next keyframe
+//!             (time: 4.0, position: (0.0, 3.0, 6.0), yaw: -90.0, pitch: -20.0, fov: 60.0,
+//!                 easing: InOut, handle: (2.0, 0.0, 0.0)),
+//!             (time: 8.0, position: (6.0, 1.0, 0.0), yaw: -180.0, pitch: 0.0, fov: 45.0),
+//!         ],
+//!     ),
+//! ],
+//! ```
+//!
+//! Angles are in degrees, and keyframes can also have a `roll`. Every value follows the same kind
+//! of curve, and yaw isn't wrapped, so a track going from 0 to 360 degrees turns all the way
+//! around. Bezier `handle`s are the offset from a keyframe's position to its outgoing control
+//! point, mirrored for the incoming one, and only shape the path. Keyframes without one, and the
+//! angles, use Catmull-Rom tangents.
+
+use cgmath::{Deg, Point3};
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraTrack {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub interpolation: Interpolation,
+    /// Starts again from the first keyframe after the last one, instead of stopping
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub looping: bool,
+    /// In order of time
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Only the flight controller rolls the camera, so it's usually left out
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub roll: f32,
+    /// Vertical field of view
+    pub fov: f32,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub easing: Easing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Interpolation {
+    Linear,
+    #[default]
+    CatmullRom,
+    Bezier,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::In => t * t,
+            Easing::Out => t * (2.0 - t),
+            Easing::InOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3<f32>,
+    pub yaw: Deg<f32>,
+    pub pitch: Deg<f32>,
+    pub roll: Deg<f32>,
+    pub fov: Deg<f32>,
+}
+
+impl CameraKeyframe {
+    fn from_pose(time: f32, pose: CameraPose) -> Self {
+        Self {
+            time,
+            position: pose.position.into(),
+            yaw: pose.yaw.0,
+            pitch: pose.pitch.0,
+            roll: pose.roll.0,
+            fov: pose.fov.0,
+            easing: Easing::Linear,
+            handle: None,
+        }
+    }
+
+    /// The values interpolated between keyframes: position, yaw, pitch, roll and field of view
+    fn channels(&self) -> [f32; 7] {
+        let [x, y, z] = self.position;
+        [x, y, z, self.yaw, self.pitch, self.roll, self.fov]
+    }
+}
+
+impl CameraTrack {
+    /// A track easing in and out from `from` to `to` over `duration` seconds, turning whichever
+    /// way round is shorter
+    pub fn between(name: String, from: CameraPose, to: CameraPose, duration: f32) -> Self {
+        let mut start = CameraKeyframe::from_pose(0.0, from);
+        start.easing = Easing::InOut;
+        let mut end = CameraKeyframe::from_pose(duration, to);
+        for (start, end) in [(start.yaw, &mut end.yaw), (start.roll, &mut end.roll)] {
+            *end = start + (*end - start + 180.0).rem_euclid(360.0) - 180.0;
+        }
+        Self {
+            name,
+            interpolation: Interpolation::Linear,
+            looping: false,
+            keyframes: vec![start, end],
+        }
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Pose `time` seconds into the track. Before the first keyframe the camera holds still at
+    /// it, and after the last it holds still there too unless the track loops.
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        let span = last.time - first.time;
+        let time = if self.looping && span > 0.0 && time > last.time {
+            first.time + (time - first.time).rem_euclid(span)
+        } else {
+            time.clamp(first.time, last.time)
+        };
+
+        let segment = self
+            .keyframes
+            .windows(2)
+            .position(|pair| time < pair[1].time)
+            .unwrap_or(self.keyframes.len().saturating_sub(2));
+        let channels = match self.keyframes.get(segment + 1) {
+            Some(end) => {
+                let start = &self.keyframes[segment];
+                let length = end.time - start.time;
+                let t = match length > 0.0 {
+                    true => start.easing.apply((time - start.time) / length),
+                    false => 1.0,
+                };
+                self.interpolate(segment, t)
+            }
+            None => first.channels(),
+        };
+
+        let [x, y, z, yaw, pitch, roll, fov] = channels;
+        Some(CameraPose {
+            position: Point3::new(x, y, z),
+            yaw: Deg(yaw),
+            pitch: Deg(pitch),
+            roll: Deg(roll),
+            fov: Deg(fov),
+        })
+    }
+
+    /// Values `t` of the way from keyframe `segment` to the next
+    fn interpolate(&self, segment: usize, t: f32) -> [f32; 7] {
+        let start = self.keyframes[segment].channels();
+        let end = self.keyframes[segment + 1].channels();
+        if self.interpolation == Interpolation::Linear {
+            return std::array::from_fn(|c| start[c] + (end[c] - start[c]) * t);
+        }
+
+        // Cubic Hermite basis, with tangents scaled to the segment's length
+        let (t2, t3) = (t * t, t * t * t);
+        let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+        let h10 = t3 - 2.0 * t2 + t;
+        let h01 = -2.0 * t3 + 3.0 * t2;
+        let h11 = t3 - t2;
+        let length = self.keyframes[segment + 1].time - self.keyframes[segment].time;
+        std::array::from_fn(|c| {
+            let start_tangent = self.tangent(segment, c, length);
+            let end_tangent = self.tangent(segment + 1, c, length);
+            h00 * start[c] + h10 * start_tangent + h01 * end[c] + h11 * end_tangent
+        })
+    }
+
+    /// Tangent of `channel` at keyframe `index`, scaled to a segment `length` seconds long
+    fn tangent(&self, index: usize, channel: usize, length: f32) -> f32 {
+        let keyframe = &self.keyframes[index];
+        if let (Interpolation::Bezier, Some(handle)) = (self.interpolation, keyframe.handle) {
+            if channel < 3 {
+                // A cubic Bezier's tangent at an end is three times the handle
+                return 3.0 * handle[channel];
+            }
+        }
+
+        // Catmull-Rom, falling back to one-sided differences at the ends of the track
+        let before = &self.keyframes[index.saturating_sub(1)];
+        let after = &self.keyframes[(index + 1).min(self.keyframes.len() - 1)];
+        let span = after.time - before.time;
+        if span <= 0.0 {
+            return 0.0;
+        }
+        (after.channels()[channel] - before.channels()[channel]) / span * length
+    }
+}
+
+/// Seconds between keyframes recorded from the live camera
+const RECORDING_INTERVAL: f32 = 0.5;
+
+/// Builds a track from the live camera, keyframing it at regular intervals
+pub struct TrackRecorder {
+    track: CameraTrack,
+    time: f32,
+}
+
+impl TrackRecorder {
+    pub fn new(name: String, pose: CameraPose) -> Self {
+        Self {
+            track: CameraTrack {
+                name,
+                interpolation: Interpolation::CatmullRom,
+                looping: false,
+                keyframes: vec![CameraKeyframe::from_pose(0.0, pose)],
+            },
+            time: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, pose: CameraPose) {
+        self.time += dt;
+        let last = self.track.keyframes.last().unwrap().time;
+        if self.time - last >= RECORDING_INTERVAL {
+            self.track
+                .keyframes
+                .push(CameraKeyframe::from_pose(self.time, pose));
+        }
+    }
+
+    /// Ends the track with a keyframe at `pose`
+    pub fn finish(mut self, pose: CameraPose) -> CameraTrack {
+        let last = self.track.keyframes.last().unwrap().time;
+        if self.time > last {
+            self.track
+                .keyframes
+                .push(CameraKeyframe::from_pose(self.time, pose));
+        }
+        self.track
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, yaw: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position: [x, 0.0, 0.0],
+            yaw,
+            pitch: 0.0,
+            roll: 0.0,
+            fov: 45.0,
+            easing: Easing::Linear,
+            handle: None,
+        }
+    }
+
+    fn track(interpolation: Interpolation) -> CameraTrack {
+        CameraTrack {
+            name: "test".to_string(),
+            interpolation,
+            looping: false,
+            keyframes: vec![
+                keyframe(0.0, 0.0, 0.0),
+                keyframe(1.0, 1.0, 180.0),
+                keyframe(3.0, 5.0, 360.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn curves_pass_through_keyframes() {
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::CatmullRom,
+            Interpolation::Bezier,
+        ] {
+            let track = track(interpolation);
+            for keyframe in &track.keyframes {
+                let pose = track.sample(keyframe.time).unwrap();
+                assert!((pose.position.x - keyframe.position[0]).abs() < 1e-5);
+                assert!((pose.yaw.0 - keyframe.yaw).abs() < 1e-3);
+            }
+            // Holds still outside the keyframes
+            assert_eq!(track.sample(-1.0), track.sample(0.0));
+            assert_eq!(track.sample(10.0), track.sample(3.0));
+        }
+    }
+
+    #[test]
+    fn tracks_ease_and_loop() {
+        let mut track = track(Interpolation::Linear);
+        assert_eq!(track.sample(0.5).unwrap().yaw, Deg(90.0));
+        track.keyframes[0].easing = Easing::In;
+        assert_eq!(track.sample(0.5).unwrap().yaw, Deg(45.0));
+
+        track.looping = true;
+        assert_eq!(track.sample(3.5), track.sample(0.5));
+    }
+
+    #[test]
+    fn transitions_turn_the_short_way() {
+        let pose = |x: f32, yaw: f32| CameraPose {
+            position: Point3::new(x, 0.0, 0.0),
+            yaw: Deg(yaw),
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+            fov: Deg(45.0),
+        };
+        let track =
+            CameraTrack::between("test".to_string(), pose(0.0, 170.0), pose(2.0, -170.0), 1.0);
+        assert_eq!(track.sample(0.5).unwrap().yaw, Deg(180.0));
+        assert_eq!(track.sample(0.5).unwrap().position.x, 1.0);
+        assert_eq!(track.sample(1.0).unwrap().yaw, Deg(190.0));
+        // Eased, so it starts off slowly
+        assert!(track.sample(0.1).unwrap().position.x < 0.2);
+    }
+
+    #[test]
+    fn bezier_handles_shape_the_path() {
+        let mut track = track(Interpolation::Bezier);
+        let smooth = track.sample(0.5).unwrap().position;
+        track.keyframes[0].handle = Some([0.0, 1.0, 0.0]);
+        let bent = track.sample(0.5).unwrap().position;
+        assert_eq!(smooth.y, 0.0);
+        assert!(bent.y > 0.0);
+    }
+}
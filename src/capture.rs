@@ -0,0 +1,231 @@
+//! Copying rendered frames back from the GPU and saving them as images.
+//!
+//! 8 bit targets read back as sRGB `Rgba8` images, and `Rgba16Float` targets as linear `Rgba32F`
+//! ones. Saving converts between the two as needed, so PNGs always hold sRGB colours and EXRs
+//! linear ones.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Target format to render in for an image saved to `path`. EXRs keep the full range of the
+/// float target, anything else is 8 bit sRGB.
+pub fn format_for(path: &Path) -> wgpu::TextureFormat {
+    if is_exr(path) {
+        wgpu::TextureFormat::Rgba16Float
+    } else {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    }
+}
+
+fn is_exr(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"))
+}
+
+/// A PNG in the working directory named after the current time, such as
+/// `screenshot-2022-08-01T12-30-15.250Z.png`
+pub fn screenshot_path() -> PathBuf {
+    let time = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+    // Colons aren't allowed in file names everywhere
+    PathBuf::from(format!("screenshot-{}.png", time).replace(':', "-"))
+}
+
+/// Copies `texture` back from the GPU, waiting until it's done
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    config: &wgpu::SurfaceConfiguration,
+) -> anyhow::Result<image::DynamicImage> {
+    Readback::start(device, queue, texture, config)?.wait(device)
+}
+
+/// A copy of a texture on its way back from the GPU. Starting one only queues the copy, so the
+/// frames after it aren't held up. The image is ready once a later `Device::poll` has mapped the
+/// buffer.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    padded_bytes_per_row: u32,
+    mapped: std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl Readback {
+    /// Copies `texture` into a buffer after the work already submitted to `queue`
+    pub fn start(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> anyhow::Result<Self> {
+        use wgpu::TextureFormat;
+
+        let bytes_per_pixel = match config.format {
+            TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8UnormSrgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8UnormSrgb => 4,
+            TextureFormat::Rgba16Float => 8,
+            other => anyhow::bail!("Can't read back {:?} textures", other),
+        };
+        // Rows in the buffer have to start on aligned offsets, so they're padded out
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (config.width * bytes_per_pixel).div_ceil(alignment) * alignment;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_buffer"),
+            size: (padded_bytes_per_row * config.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, mapped) = std::sync::mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // The readback may have been dropped, in which case nobody wants the result
+                let _ = sender.send(result);
+            });
+
+        Ok(Self {
+            buffer,
+            format: config.format,
+            width: config.width,
+            height: config.height,
+            bytes_per_pixel,
+            padded_bytes_per_row,
+            mapped,
+        })
+    }
+
+    /// Returns the image if the copy has finished, without waiting for it
+    pub fn try_finish(&self) -> Option<anyhow::Result<image::DynamicImage>> {
+        use std::sync::mpsc::TryRecvError;
+
+        match self.mapped.try_recv() {
+            Ok(result) => Some(self.finish(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(anyhow::anyhow!("The readback buffer was never mapped")))
+            }
+        }
+    }
+
+    /// Blocks until the copy has finished
+    pub fn wait(self, device: &wgpu::Device) -> anyhow::Result<image::DynamicImage> {
+        device.poll(wgpu::Maintain::Wait);
+        let result = self.mapped.recv()?;
+        self.finish(result)
+    }
+
+    fn finish(
+        &self,
+        mapped: Result<(), wgpu::BufferAsyncError>,
+    ) -> anyhow::Result<image::DynamicImage> {
+        use wgpu::TextureFormat;
+
+        mapped.context("Couldn't map the readback buffer")?;
+
+        let unpadded_bytes_per_row = self.width * self.bytes_per_pixel;
+        let slice = self.buffer.slice(..);
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+        for row in data.chunks(self.padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        drop(data);
+        self.buffer.unmap();
+
+        let image = match self.format {
+            TextureFormat::Rgba16Float => {
+                let pixels = pixels
+                    .chunks_exact(2)
+                    .map(|half| half::f16::from_le_bytes([half[0], half[1]]).to_f32())
+                    .collect();
+                image::DynamicImage::ImageRgba32F(
+                    image::Rgba32FImage::from_raw(self.width, self.height, pixels).unwrap(),
+                )
+            }
+            format => {
+                if matches!(
+                    format,
+                    TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+                ) {
+                    for pixel in pixels.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                }
+                image::DynamicImage::ImageRgba8(
+                    image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap(),
+                )
+            }
+        };
+        Ok(image)
+    }
+}
+
+/// Saves `image` as the format `path`'s extension names
+pub fn save(image: &image::DynamicImage, path: &Path) -> anyhow::Result<()> {
+    let image = match (image, is_exr(path)) {
+        (image::DynamicImage::ImageRgba8(image), true) => image::DynamicImage::ImageRgba32F(
+            image::Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+                let [r, g, b, a] = image.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+                image::Rgba([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a])
+            }),
+        ),
+        (image::DynamicImage::ImageRgba32F(image), false) => image::DynamicImage::ImageRgba8(
+            image::RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                let [r, g, b, a] = image.get_pixel(x, y).0;
+                image::Rgba(
+                    [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a]
+                        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+                )
+            }),
+        ),
+        (image, _) => image.clone(),
+    };
+    image
+        .save(path)
+        .with_context(|| format!("Couldn't save image {:?}", path))
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
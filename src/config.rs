@@ -0,0 +1,53 @@
+//! User settings, read from `flashbang.ron` in the working directory or the file given with
+//! `--config`. Everything in it is optional:
+//!
+//! ```ron
+//! (
+//!     // Rebinds input actions, described in `input`
+//!     bindings: {
+//!         "move_forward": [Key(Z), Key(Up)],
+//!     },
+//!     // Seconds the camera takes to glide to a recalled bookmark. Zero jumps straight there.
+//!     bookmark_transition: 0.75,
+//! )
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::input::Binding;
+
+/// Read when no config file is given on the command line, if it exists
+pub const DEFAULT_CONFIG: &str = "flashbang.ron";
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Bindings for each action named, in place of its defaults
+    pub bindings: BTreeMap<String, Vec<Binding>>,
+    /// Seconds spent moving to a recalled bookmark
+    pub bookmark_transition: f32,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read config file {:?}", path))?;
+        Self::parse(&text).with_context(|| format!("Couldn't parse config file {:?}", path))
+    }
+
+    /// Loads `DEFAULT_CONFIG`, or the default settings if there isn't one
+    pub fn load_default() -> anyhow::Result<Self> {
+        match Path::new(DEFAULT_CONFIG).exists() {
+            true => Self::load(DEFAULT_CONFIG),
+            false => Ok(Self::default()),
+        }
+    }
+}
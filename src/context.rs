@@ -1,13 +1,21 @@
 use anyhow::{Context, Result};
 use winit::window::Window;
 
+/// Where frames end up
+pub enum RenderTarget {
+    Surface(wgpu::Surface),
+    /// Rendered into a texture, which can be read back with `State::read_frame`
+    Offscreen(wgpu::Texture),
+}
+
 pub struct RenderContext {
-    instance: wgpu::Instance,
-    surface: wgpu::Surface,
-    adapter: wgpu::Adapter,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub target: RenderTarget,
+    /// Also describes offscreen targets, which are never presented
+    pub config: wgpu::SurfaceConfiguration,
 }
 
 impl RenderContext {
@@ -22,18 +30,7 @@ impl RenderContext {
             })
             .await
             .context("Adapter creation failed")?;
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_LINE,
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .context("Device creation failed")?;
+        let (device, queue) = request_device(&adapter).await?;
 
         let winit::dpi::PhysicalSize { width, height } = window.inner_size();
         let config = wgpu::SurfaceConfiguration {
@@ -49,11 +46,91 @@ impl RenderContext {
 
         Ok(Self {
             instance,
-            surface,
             adapter,
             device,
             queue,
+            target: RenderTarget::Surface(surface),
             config,
         })
     }
+
+    /// Creates a context without a window, rendering into a `width` by `height` texture of
+    /// `format`. `force_fallback_adapter` picks a software adapter, for machines without a GPU.
+    pub async fn headless(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        force_fallback_adapter: bool,
+    ) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .with_context(|| match force_fallback_adapter {
+                true => "No software adapter available",
+                false => "Adapter creation failed",
+            })?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format,
+            present_mode: wgpu::PresentMode::Fifo,
+            width,
+            height,
+        };
+        let texture = create_offscreen_texture(&device, &config);
+
+        log::debug!(
+            "Created headless RenderContext on {:?}: {:#?}",
+            adapter.get_info(),
+            config
+        );
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            target: RenderTarget::Offscreen(texture),
+            config,
+        })
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                label: None,
+            },
+            None,
+        )
+        .await
+        .context("Device creation failed")
+}
+
+pub fn create_offscreen_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("offscreen_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage,
+    })
 }
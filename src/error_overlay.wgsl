@@ -0,0 +1,18 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @location(0) pos: vec3<f32>
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(pos, 1.0);
+    return out;
+}
+
+// Drawn over the frame while a shader or scene file that failed to reload is still broken
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 0.0, 0.2);
+}
@@ -1,10 +1,5 @@
-struct CameraUniform {
-    pos: vec3<f32>,
-    dir: vec3<f32>,
-    right: vec3<f32>,
-    up: vec3<f32>,
-    aspect: f32,
-};
+#include "uniforms.wgsl"
+
 @group(1)
 @binding(0)
 var<uniform> camera: CameraUniform;
@@ -26,17 +21,16 @@ fn vs_main(
 
 // Fragment shader
 
-struct Light {
-    position: vec3<f32>,
-    colour: vec3<f32>,
-    strength: f32,
-    radius: f32,
-}
 @group(3)
 @binding(0)
 var<storage, read> lights: array<Light>;
 
+@group(3)
+@binding(1)
+var<storage, read> sdf_objects: array<SdfObject>;
+
 struct FragmentInput {
+    @builtin(position) frag_coord: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
 };
 
@@ -82,38 +76,73 @@ var t_skybox: texture_cube<f32>;
 @binding(11)
 var s_skybox: sampler;
 
+@group(0)
+@binding(12)
+var t_specular: texture_2d<f32>;
+@group(0)
+@binding(13)
+var s_specular: sampler;
+
+@group(0)
+@binding(14)
+var t_emissive: texture_2d<f32>;
+@group(0)
+@binding(15)
+var s_emissive: sampler;
+
 @group(2)
 @binding(0)
 var<uniform> frame_count: f32;
 
+#include "sdf.wgsl"
+
 let NUMBER_OF_STEPS: i32 = 128;
 let MINIMUM_HIT_DISTANCE: f32 = 0.001;
 let MAXIMUM_TRACE_DISTANCE: f32 = 1000.0;
 let EPSILON: f32 = 0.0001;
+let AMBIENT: f32 = 0.05;
+let SPECULAR_STRENGTH: f32 = 0.5;
+let SHININESS: f32 = 32.0;
+let PI: f32 = 3.14159265;
 
-fn sd_sphere(p: vec3<f32>, r: f32) -> f32 {
-    return length(p) - r;
-}
-
-fn sd_box(p: vec3<f32>, b: vec3<f32>) -> f32 {
-    let q = abs(p) - b;
-    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
-}
-
-fn op_union(d1: f32, d2: f32) -> f32 {
-    return min(d1, d2);
-}
-
-fn op_difference(d1: f32, d2: f32) -> f32 {
-    return max(d1, -d2);
-}
-
-fn op_intersect(d1: f32, d2: f32) -> f32 {
-    return max(d1, d2);
+fn sd_object(object: SdfObject, p: vec3<f32>) -> f32 {
+    let local_p = (object.inverse_transform * vec4<f32>(p, 1.0)).xyz;
+    var d = MAXIMUM_TRACE_DISTANCE;
+    switch (object.shape) {
+        case 1u: {
+            d = sd_sphere(local_p, object.params.x);
+        }
+        case 2u: {
+            d = sd_box(local_p, object.params.xyz);
+        }
+        default: {}
+    }
+    return d * object.scale;
 }
 
+// Combines every object in the scene, in order, with its operation
 fn scene(p: vec3<f32>) -> f32 {
-    return op_intersect(sd_box(p, vec3<f32>(1.0, 0.8, 0.7)), sd_sphere(p, 0.9));
+    var d = MAXIMUM_TRACE_DISTANCE;
+    let object_count = arrayLength(&sdf_objects);
+    for (var i = 0u; i < object_count; i++) {
+        let object = sdf_objects[i];
+        if (object.shape == 0u) {
+            continue;
+        }
+        let object_distance = sd_object(object, p);
+        switch (object.operation) {
+            case 1u: {
+                d = op_difference(d, object_distance);
+            }
+            case 2u: {
+                d = op_intersect(d, object_distance);
+            }
+            default: {
+                d = op_union(d, object_distance);
+            }
+        }
+    }
+    return d;
 }
 
 fn estimate_normal(p: vec3<f32>) -> vec3<f32> {
@@ -126,30 +155,194 @@ fn estimate_normal(p: vec3<f32>) -> vec3<f32> {
     );
 }
 
-fn ray_march(ro: vec3<f32>, rd: vec3<f32>) -> vec3<f32> {
+// Blinn-Phong lighting from every light in the lights buffer. Shared by SDF hits and by the
+// raster geometry read back from the G-buffer so both are lit identically.
+fn shade(
+    p: vec3<f32>,
+    n: vec3<f32>,
+    albedo: vec3<f32>,
+    specular_colour: vec3<f32>,
+    shininess: f32,
+    view_dir: vec3<f32>,
+) -> vec3<f32> {
+    var colour = AMBIENT * albedo;
+    let light_count = arrayLength(&lights);
+    for (var i = 0u; i < light_count; i++) {
+        let light = lights[i];
+        // Also skips the placeholder used when the scene has no lights
+        if (light.strength <= 0.0) {
+            continue;
+        }
+        let to_light = light.position - p;
+        let light_distance = length(to_light);
+        let l = to_light / light_distance;
+        let falloff = light_distance / light.radius;
+        let attenuation = light.strength / (1.0 + falloff * falloff);
+
+        let diffuse = max(dot(n, l), 0.0);
+        let h = normalize(l + view_dir);
+        let specular = specular_colour * pow(max(dot(n, h), 0.0), shininess);
+        colour += (albedo * diffuse + specular) * light.colour * attenuation;
+    }
+    return colour;
+}
+
+// Returns the distance along the ray to the closest SDF surface, or a negative value on a miss
+fn ray_march(ro: vec3<f32>, rd: vec3<f32>) -> f32 {
     var total_distance_travelled = 0.0;
-    let skybox = textureSample(t_skybox, s_skybox, rd).xyz; 
-    
+
     for (var i = 0; i < NUMBER_OF_STEPS; i++) {
         let current_position = ro + total_distance_travelled * rd;
         let distance_to_closest = scene(current_position);
         if (distance_to_closest < MINIMUM_HIT_DISTANCE) {
-            let albedo = estimate_normal(current_position);
-            return (albedo + 1.0) / 2.0;
+            return total_distance_travelled;
         }
         if (total_distance_travelled > MAXIMUM_TRACE_DISTANCE) {
             break;
         }
         total_distance_travelled += distance_to_closest;
     }
-    
-    return skybox;
+
+    return -1.0;
+}
+
+struct Ray {
+    origin: vec3<f32>,
+    direction: vec3<f32>,
+};
+
+// Moves the ray's origin by `offset`, keeping the point where it crosses the plane `distance` in
+// front of the camera. Views too wide for a plane keep a point on a sphere instead.
+fn offset_ray(ray: Ray, offset: vec3<f32>, distance: f32) -> Ray {
+    if (all(offset == vec3<f32>(0.0))) {
+        return ray;
+    }
+    var along = distance;
+    if (camera.projection < 2u) {
+        along /= dot(ray.direction, camera.dir);
+    }
+    let fixed_point = ray.origin + along * ray.direction;
+    var moved: Ray;
+    moved.origin = ray.origin + offset;
+    moved.direction = normalize(fixed_point - moved.origin);
+    return moved;
+}
+
+// Primary ray through `screen`, which runs from -1 at the bottom of the frame to 1 at the top, and
+// across by the aspect ratio. The direction is zero outside a fisheye's image circle.
+fn camera_ray(screen: vec2<f32>) -> Ray {
+    var ray: Ray;
+    ray.origin = camera.pos;
+    switch (camera.projection) {
+        // Orthographic
+        case 1u: {
+            let offset = screen * camera.view_scale;
+            ray.origin += offset.x * camera.right + offset.y * camera.up;
+            ray.direction = camera.dir;
+        }
+        // Fisheye
+        case 2u: {
+            // Angle from the centre grows in proportion to distance from it
+            let radius = length(screen);
+            let angle = radius * camera.view_scale;
+            if (radius > 1.0) {
+                return ray;
+            } else if (radius == 0.0) {
+                ray.direction = camera.dir;
+            } else {
+                let across = (screen.x * camera.right + screen.y * camera.up) / radius;
+                ray.direction = cos(angle) * camera.dir + sin(angle) * across;
+            }
+        }
+        // Equirectangular
+        case 3u: {
+            // The full width is all the way around and the height is pole to pole, with the
+            // camera's direction in the middle
+            let longitude = screen.x / camera.aspect * PI;
+            let latitude = screen.y * PI / 2.0;
+            let horizontal = cos(longitude) * camera.dir + sin(longitude) * camera.right;
+            ray.direction = cos(latitude) * horizontal + sin(latitude) * camera.up;
+        }
+        // Perspective
+        default: {
+            let offset = screen * camera.view_scale;
+            ray.direction = normalize(camera.dir + offset.x * camera.right + offset.y * camera.up);
+        }
+    }
+
+    // Stereo eyes converge where the screen appears to be, and a thin lens focuses beyond that
+    ray = offset_ray(ray, camera.eye_offset, camera.convergence);
+    return offset_ray(ray, camera.lens_offset, camera.focus_distance);
+}
+
+// Colour seen along `ray` at the pixel `coords`, with the distance to what it hit in w
+fn trace(ray: Ray, coords: vec2<i32>, skybox: vec3<f32>) -> vec4<f32> {
+    let raster_position = textureLoad(t_position, coords, 0);
+    var raster_distance = MAXIMUM_TRACE_DISTANCE;
+    if (raster_position.w > 0.0) {
+        raster_distance = distance(raster_position.xyz, ray.origin);
+    }
+
+    let sdf_distance = ray_march(ray.origin, ray.direction);
+    if (sdf_distance >= 0.0 && sdf_distance < raster_distance) {
+        let hit = ray.origin + sdf_distance * ray.direction;
+        let normal = estimate_normal(hit);
+        let albedo = (normal + 1.0) / 2.0;
+        let specular = vec3<f32>(SPECULAR_STRENGTH);
+        let lit = shade(hit, normal, albedo, specular, SHININESS, -ray.direction);
+        return vec4<f32>(lit, sdf_distance);
+    }
+
+    if (raster_position.w > 0.0) {
+        let albedo = textureLoad(t_albedo, coords, 0).xyz;
+        let normal = textureLoad(t_normal, coords, 0);
+        let specular = textureLoad(t_specular, coords, 0).xyz;
+        let emissive = textureLoad(t_emissive, coords, 0).xyz;
+        let view_dir = normalize(ray.origin - raster_position.xyz);
+        let lit = shade(
+            raster_position.xyz,
+            normalize(normal.xyz),
+            albedo,
+            specular,
+            normal.w,
+            view_dir,
+        );
+        return vec4<f32>(lit + emissive, raster_distance);
+    }
+
+    return vec4<f32>(skybox, MAXIMUM_TRACE_DISTANCE);
 }
-  
+
+struct FragmentOutput {
+    @location(0) colour: vec4<f32>,
+    // Average of every frame since the view last changed, with this frame's distance along the
+    // ray in w for autofocus
+    @location(1) accumulated: vec4<f32>,
+};
+
 @fragment
-fn fs_main(in: FragmentInput) -> @location(0) vec4<f32> {
-    let p = vec2<f32>(in.tex_coords.x * camera.aspect, in.tex_coords.y);
-    let ray_dir = normalize(p.x * camera.right + p.y * camera.up + 1.5 * camera.dir);
-    let albedo = ray_march(camera.pos, ray_dir);
-    return vec4<f32>(albedo, 1.0);
+fn fs_main(in: FragmentInput) -> FragmentOutput {
+    var ray = camera_ray(vec2<f32>(in.tex_coords.x * camera.aspect, -in.tex_coords.y));
+    let outside_image = all(ray.direction == vec3<f32>(0.0));
+    if (outside_image) {
+        ray.direction = camera.dir;
+    }
+    // Sampled up here since sampling needs uniform control flow
+    let skybox = textureSample(t_skybox, s_skybox, ray.direction).xyz;
+
+    let coords = vec2<i32>(in.frag_coord.xy);
+    var colour = vec4<f32>(0.0, 0.0, 0.0, MAXIMUM_TRACE_DISTANCE);
+    if (!outside_image) {
+        colour = trace(ray, coords, skybox);
+    }
+
+    var out: FragmentOutput;
+    out.accumulated = colour;
+    if (frame_count > 0.0) {
+        let last = textureLoad(t_last_frame, coords, 0).xyz;
+        let average = (last * frame_count + colour.xyz) / (frame_count + 1.0);
+        out.accumulated = vec4<f32>(average, colour.w);
+    }
+    out.colour = vec4<f32>(out.accumulated.xyz, 1.0);
+    return out;
 }
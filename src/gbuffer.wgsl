@@ -0,0 +1,124 @@
+#include "uniforms.wgsl"
+
+@group(1)
+@binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = normal_matrix * model.tangent;
+    out.world_bitangent = normal_matrix * model.bitangent;
+    return out;
+}
+
+// Fragment shader
+
+@group(0)
+@binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)
+@binding(1)
+var s_diffuse: sampler;
+@group(0)
+@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0)
+@binding(3)
+var s_normal: sampler;
+@group(0)
+@binding(4)
+var t_specular: texture_2d<f32>;
+@group(0)
+@binding(5)
+var s_specular: sampler;
+@group(0)
+@binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0)
+@binding(7)
+var s_emissive: sampler;
+
+@group(0)
+@binding(8)
+var<uniform> material: MaterialUniform;
+
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    // w is 1.0 wherever geometry was written so the lighting pass can tell it from the clear value
+    @location(1) position: vec4<f32>,
+    // w is the specular exponent
+    @location(2) normal: vec4<f32>,
+    @location(3) specular: vec4<f32>,
+    @location(4) emissive: vec4<f32>,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> GBufferOutput {
+    var out: GBufferOutput;
+    // Tangent space normal from the normal map, moved into world space
+    let tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+
+    let diffuse = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let specular = textureSample(t_specular, s_specular, in.tex_coords).xyz;
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).xyz;
+
+    out.albedo = vec4<f32>(diffuse.xyz * material.diffuse, diffuse.a * material.dissolve);
+    out.position = vec4<f32>(in.world_position, 1.0);
+    out.normal = vec4<f32>(normalize(tangent_matrix * tangent_normal), material.shininess);
+    out.specular = vec4<f32>(specular * material.specular, 0.0);
+    out.emissive = vec4<f32>(emissive * material.emissive, 0.0);
+    return out;
+}
@@ -0,0 +1,160 @@
+//! Golden image tests.
+//!
+//! Every scene in `tests/golden/` is rendered headless on the software adapter and compared with
+//! the PNG of the same name next to it. Software rendering means the tests run on machines
+//! without a GPU, as long as Mesa's llvmpipe or lavapipe is installed.
+//!
+//! Rasterisers don't agree to the last bit, so pixels are compared by their CIE76 colour
+//! difference, and a scene only fails when more than a small fraction of its pixels are visibly
+//! different. Failures write the render and a diff image highlighting the changed pixels to
+//! `target/golden/`. Run with `FLASHBANG_UPDATE_GOLDEN=1` to replace the references after an
+//! intended change.
+
+use std::path::{Path, PathBuf};
+
+use crate::headless::{self, HeadlessOptions};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+/// Colour difference above which a pixel counts as changed. Around 2.3 is just noticeable.
+const MAX_DELTA_E: f32 = 3.0;
+/// Fraction of the pixels that may change before the image does
+const MAX_CHANGED_FRACTION: f32 = 0.002;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target")
+        .join("golden")
+}
+
+#[test]
+fn golden_images_match() {
+    let mut scenes: Vec<_> = std::fs::read_dir(golden_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect();
+    scenes.sort();
+    assert!(!scenes.is_empty(), "No scenes in {:?}", golden_dir());
+
+    let update = std::env::var_os("FLASHBANG_UPDATE_GOLDEN").is_some();
+    let options = HeadlessOptions {
+        width: WIDTH,
+        height: HEIGHT,
+        force_fallback_adapter: true,
+        ..Default::default()
+    };
+
+    // Checks every scene before failing, so one run reports all of the changes
+    let mut failures = Vec::new();
+    for scene in scenes {
+        let name = scene.file_stem().unwrap().to_string_lossy().into_owned();
+        let reference_path = scene.with_extension("png");
+        let actual = headless::render_image(Some(scene.clone()), &options)
+            .unwrap_or_else(|e| panic!("Couldn't render {:?}: {:?}", scene, e))
+            .into_rgba8();
+
+        if update {
+            actual.save(&reference_path).unwrap();
+            continue;
+        }
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.into_rgba8(),
+            Err(e) => {
+                failures.push(format!(
+                    "{}: couldn't open the reference {:?}: {}",
+                    name, reference_path, e
+                ));
+                continue;
+            }
+        };
+        if reference.dimensions() != actual.dimensions() {
+            failures.push(format!(
+                "{}: rendered at {:?}, but the reference is {:?}",
+                name,
+                actual.dimensions(),
+                reference.dimensions()
+            ));
+            continue;
+        }
+
+        let (changed, diff) = compare(&reference, &actual);
+        let changed_fraction = changed as f32 / (WIDTH * HEIGHT) as f32;
+        if changed_fraction > MAX_CHANGED_FRACTION {
+            let dir = output_dir();
+            std::fs::create_dir_all(&dir).unwrap();
+            let actual_path = dir.join(format!("{}.png", name));
+            let diff_path = dir.join(format!("{}.diff.png", name));
+            actual.save(&actual_path).unwrap();
+            diff.save(&diff_path).unwrap();
+            failures.push(format!(
+                "{}: {} pixels ({:.2}%) changed, see {:?} and {:?}",
+                name,
+                changed,
+                changed_fraction * 100.0,
+                actual_path,
+                diff_path
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "Golden images don't match:\n{}\nRun with FLASHBANG_UPDATE_GOLDEN=1 if the changes are intended",
+        failures.join("\n")
+    );
+}
+
+/// Counts the visibly changed pixels, and draws them in red over a faded copy of the reference
+fn compare(reference: &image::RgbaImage, actual: &image::RgbaImage) -> (usize, image::RgbaImage) {
+    let mut changed = 0;
+    let diff = image::RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let expected = reference.get_pixel(x, y).0;
+        let delta_e = delta_e(expected, actual.get_pixel(x, y).0);
+        if delta_e > MAX_DELTA_E {
+            changed += 1;
+            let strength = (128.0 + delta_e * 4.0).min(255.0) as u8;
+            image::Rgba([strength, 0, 0, 255])
+        } else {
+            let [l, _, _] = lab(expected);
+            let grey = (l / 100.0 * 64.0) as u8;
+            image::Rgba([grey, grey, grey, 255])
+        }
+    });
+    (changed, diff)
+}
+
+fn delta_e(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let [l1, a1, b1] = lab(a);
+    let [l2, a2, b2] = lab(b);
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+/// CIELAB coordinates of an sRGB colour, under a D65 white point
+fn lab([r, g, b, _]: [u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [r, g, b].map(|c| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let [fx, fy, fz] = [x, y, z].map(|t| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    });
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
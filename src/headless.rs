@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use crate::camera::{ProjectionMode, StereoMode};
+use crate::context::RenderContext;
+use crate::recording::FrameWriter;
+use crate::state::State;
+
+/// Settings for rendering without a window
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    /// Render on a software adapter, for machines without a GPU
+    pub force_fallback_adapter: bool,
+    /// Replaces the camera position from the scene file
+    pub camera_position: Option<cgmath::Point3<f32>>,
+    /// Points the camera at this instead of the scene file's direction
+    pub camera_target: Option<cgmath::Point3<f32>>,
+    /// Plays the scene's camera track with this name, from its start
+    pub camera_track: Option<String>,
+    /// Starts from one of the bookmarks saved beside the scene, rather than its camera
+    pub bookmark: Option<u32>,
+    /// Replaces the scene file's projection mode
+    pub projection: Option<ProjectionMode>,
+    /// Replaces the scene file's lens with one this wide
+    pub aperture: Option<f32>,
+    /// Fixes the lens's focus, rather than leaving it to autofocus
+    pub focus_distance: Option<f32>,
+    /// Replaces the scene file's stereo mode
+    pub stereo: Option<StereoMode>,
+    /// Distance between the stereo eyes
+    pub interocular: Option<f32>,
+    /// Distance at which the stereo eyes' views meet
+    pub convergence: Option<f32>,
+    /// Frames rendered before the last one is read back. Each looks through a different point on
+    /// the lens, so depth of field wants plenty, while a pinhole converges in a single frame.
+    pub samples: u32,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            force_fallback_adapter: false,
+            camera_position: None,
+            camera_target: None,
+            camera_track: None,
+            bookmark: None,
+            projection: None,
+            aperture: None,
+            focus_distance: None,
+            stereo: None,
+            interocular: None,
+            convergence: None,
+            samples: 1,
+        }
+    }
+}
+
+/// Renders a frame of the scene at `scene_path`, or of the default scene, offscreen
+pub fn render_image(
+    scene_path: Option<PathBuf>,
+    options: &HeadlessOptions,
+) -> anyhow::Result<image::DynamicImage> {
+    let (mut state, _) = create_state(scene_path, options)?;
+    render_frame(&mut state, std::time::Duration::ZERO, options)?;
+    state.read_frame()
+}
+
+/// Records the scene offscreen into `writer`, advancing by a fixed `1 / fps` seconds each frame
+/// however long they take to render. Without a number of `frames`, records the whole of the
+/// camera track being played, or a single frame.
+pub fn record(
+    scene_path: Option<PathBuf>,
+    options: &HeadlessOptions,
+    frames: Option<u32>,
+    fps: u32,
+    mut writer: FrameWriter,
+) -> anyhow::Result<()> {
+    let (mut state, track_duration) = create_state(scene_path, options)?;
+    let frames = frames.unwrap_or_else(|| (track_duration * fps as f32).ceil() as u32 + 1);
+    let timestep = std::time::Duration::from_secs_f64(1.0 / fps as f64);
+    for frame in 0..frames {
+        let dt = match frame {
+            0 => std::time::Duration::ZERO,
+            _ => timestep,
+        };
+        render_frame(&mut state, dt, options)?;
+        writer.write(&state.read_frame()?)?;
+    }
+    writer.finish()
+}
+
+/// Also returns the duration of the camera track being played, or 0 without one
+fn create_state(
+    scene_path: Option<PathBuf>,
+    options: &HeadlessOptions,
+) -> anyhow::Result<(State, f32)> {
+    let context = pollster::block_on(RenderContext::headless(
+        options.width,
+        options.height,
+        options.format,
+        options.force_fallback_adapter,
+    ))?;
+    let mut state = pollster::block_on(State::new(context, scene_path, false))?;
+    if options.camera_position.is_some() || options.camera_target.is_some() {
+        state.override_camera(options.camera_position, options.camera_target)?;
+    }
+    if let Some(slot) = options.bookmark {
+        state.recall_bookmark(slot)?;
+    }
+    if let Some(mode) = options.projection {
+        state.set_projection_mode(mode);
+    }
+    if options.aperture.is_some() || options.focus_distance.is_some() {
+        let mut lens = state.lens().clone();
+        lens.aperture = options.aperture.unwrap_or(lens.aperture);
+        lens.focus_distance = options.focus_distance.or(lens.focus_distance);
+        state.set_lens(lens);
+    }
+    if options.stereo.is_some() || options.interocular.is_some() || options.convergence.is_some() {
+        let mut stereo = state.stereo().clone();
+        stereo.mode = options.stereo.unwrap_or(stereo.mode);
+        stereo.interocular = options.interocular.unwrap_or(stereo.interocular);
+        stereo.convergence = options.convergence.unwrap_or(stereo.convergence);
+        state.set_stereo(stereo);
+    }
+    let track_duration = match &options.camera_track {
+        Some(name) => state.play_camera_track(name)?,
+        None => 0.0,
+    };
+    Ok((state, track_duration))
+}
+
+/// Advances the scene by `dt`, then renders it `options.samples` times
+fn render_frame(
+    state: &mut State,
+    dt: std::time::Duration,
+    options: &HeadlessOptions,
+) -> anyhow::Result<()> {
+    state.update(dt);
+    state.render()?;
+    // Autofocus measures the first frame, and the samples only count once it's in focus
+    if state.wait_for_autofocus() {
+        state.update(std::time::Duration::ZERO);
+        state.render()?;
+    }
+    for _ in 1..options.samples {
+        state.update(std::time::Duration::ZERO);
+        state.render()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,327 @@
+//! Maps keys and mouse buttons to named actions.
+//!
+//! Each part of the app registers the actions it responds to, along with their default bindings,
+//! and only ever sees actions, never keys. The config file can rebind any action, replacing all
+//! of its defaults:
+//!
+//! ```ron
+//! bindings: {
+//!     // AZERTY
+//!     "move_forward": [Key(Z), Key(Up)],
+//!     "move_left": [Key(Q), Key(Left)],
+//!     "roll_left": [Key(A)],
+//!     "look": [Mouse(Right)],
+//!     // No bindings at all turns an action off
+//!     "quit": [],
+//! },
+//! ```
+//!
+//! Keys are winit's `VirtualKeyCode` names, and mouse buttons are `Left`, `Right`, `Middle` or
+//! `Other(n)`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+pub const MOVE_FORWARD: &str = "move_forward";
+pub const MOVE_BACKWARD: &str = "move_backward";
+pub const MOVE_LEFT: &str = "move_left";
+pub const MOVE_RIGHT: &str = "move_right";
+pub const MOVE_UP: &str = "move_up";
+pub const MOVE_DOWN: &str = "move_down";
+pub const SPRINT: &str = "sprint";
+pub const SLOW: &str = "slow";
+pub const ROLL_LEFT: &str = "roll_left";
+pub const ROLL_RIGHT: &str = "roll_right";
+/// Turning the fly and flight cameras while held
+pub const LOOK: &str = "look";
+pub const ORBIT: &str = "orbit";
+pub const PAN: &str = "pan";
+pub const QUIT: &str = "quit";
+pub const SAVE_SCENE: &str = "save_scene";
+pub const SCREENSHOT: &str = "screenshot";
+pub const RECORD_CAMERA_TRACK: &str = "record_camera_track";
+pub const PLAY_CAMERA_TRACK: &str = "play_camera_track";
+pub const NEXT_CAMERA_TRACK: &str = "next_camera_track";
+pub const NEXT_CAMERA_CONTROLLER: &str = "next_camera_controller";
+pub const NEXT_PROJECTION: &str = "next_projection";
+pub const NEXT_STEREO_MODE: &str = "next_stereo_mode";
+/// Held while pressing a bookmark's key to store the view there, rather than going to it
+pub const STORE_BOOKMARK: &str = "store_bookmark";
+/// Every action above, for checking that each is registered with a default binding
+#[cfg(test)]
+pub const ALL: &[&str] = &[
+    MOVE_FORWARD,
+    MOVE_BACKWARD,
+    MOVE_LEFT,
+    MOVE_RIGHT,
+    MOVE_UP,
+    MOVE_DOWN,
+    SPRINT,
+    SLOW,
+    ROLL_LEFT,
+    ROLL_RIGHT,
+    LOOK,
+    ORBIT,
+    PAN,
+    QUIT,
+    SAVE_SCENE,
+    SCREENSHOT,
+    RECORD_CAMERA_TRACK,
+    PLAY_CAMERA_TRACK,
+    NEXT_CAMERA_TRACK,
+    NEXT_CAMERA_CONTROLLER,
+    NEXT_PROJECTION,
+    NEXT_STEREO_MODE,
+    STORE_BOOKMARK,
+];
+/// Going to bookmarks 0 to 9
+pub const BOOKMARKS: [&str; 10] = [
+    "bookmark_0",
+    "bookmark_1",
+    "bookmark_2",
+    "bookmark_3",
+    "bookmark_4",
+    "bookmark_5",
+    "bookmark_6",
+    "bookmark_7",
+    "bookmark_8",
+    "bookmark_9",
+];
+
+/// Actions that can share default bindings, because they belong to different camera controllers
+/// and only the active controller hears about its actions
+const STACKING: &[&[&str]] = &[&[LOOK, ORBIT]];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// An action starting or stopping
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ActionEvent {
+    pub action: &'static str,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct InputMap {
+    /// Every registered action with its default bindings, in the order they were registered
+    actions: Vec<(&'static str, Vec<Binding>)>,
+    /// Bindings from the config file, replacing the defaults of the actions they name
+    overrides: BTreeMap<String, Vec<Binding>>,
+    /// Actions each binding triggers, built from the two above
+    bound: HashMap<Binding, Vec<&'static str>>,
+    held: HashSet<Binding>,
+    /// How many of each action's bindings are held, so it only stops when the last is let go
+    held_actions: HashMap<&'static str, usize>,
+}
+
+impl InputMap {
+    pub fn new(overrides: BTreeMap<String, Vec<Binding>>) -> Self {
+        Self {
+            overrides,
+            ..Default::default()
+        }
+    }
+
+    /// Adds an action, bound to `defaults` unless the config file binds it. Registering an action
+    /// again keeps its first defaults, so anything sharing an action can register it. Defaults
+    /// that are already another action's get a warning, unless the two are in `STACKING`.
+    pub fn register(&mut self, action: &'static str, defaults: &[Binding]) {
+        if self.actions.iter().any(|(name, _)| *name == action) {
+            return;
+        }
+        for (binding, other) in clashes(&self.actions, action, defaults) {
+            eprintln!(
+                "{:?} is the default binding of both {:?} and {:?}",
+                binding, other, action
+            );
+        }
+        self.actions.push((action, defaults.to_vec()));
+        let bindings = self
+            .overrides
+            .get(action)
+            .map_or(defaults, |bindings| bindings.as_slice());
+        for binding in bindings {
+            self.bound.entry(*binding).or_default().push(action);
+        }
+    }
+
+    /// Every pair of actions with a default binding in common that aren't meant to stack, and
+    /// the binding
+    #[cfg(test)]
+    pub fn clashing_defaults(&self) -> Vec<(&'static str, &'static str, Binding)> {
+        self.actions
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (action, defaults))| {
+                clashes(&self.actions[..index], action, defaults)
+                    .into_iter()
+                    .map(|(binding, other)| (other, *action, binding))
+            })
+            .collect()
+    }
+
+    /// Config file actions that nothing registered, which are probably typos
+    pub fn unknown_actions(&self) -> impl Iterator<Item = &str> {
+        self.overrides
+            .keys()
+            .map(String::as_str)
+            .filter(|name| self.actions.iter().all(|(action, _)| action != name))
+    }
+
+    /// Turns a key or button changing state into the actions bound to it. Key repeats don't
+    /// start an action again, and an action held by several bindings only stops when the last
+    /// of them is released.
+    pub fn process(&mut self, binding: Binding, state: ElementState) -> Vec<ActionEvent> {
+        let pressed = state == ElementState::Pressed;
+        let changed = match pressed {
+            true => self.held.insert(binding),
+            false => self.held.remove(&binding),
+        };
+        if !changed {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        for &action in self.bound.get(&binding).into_iter().flatten() {
+            let count = self.held_actions.entry(action).or_default();
+            let was_held = *count > 0;
+            match pressed {
+                true => *count += 1,
+                false => *count = count.saturating_sub(1),
+            }
+            if was_held != (*count > 0) {
+                events.push(ActionEvent { action, pressed });
+            }
+        }
+        events
+    }
+
+    /// Actions whose bindings are held down
+    pub fn held_actions(&self) -> Vec<&'static str> {
+        let mut actions: Vec<_> = self
+            .held_actions
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .map(|(&action, _)| action)
+            .collect();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// Lets go of everything held, for when the window loses focus and won't hear about keys
+    /// being released
+    pub fn release_all(&mut self) -> Vec<ActionEvent> {
+        let actions = self.held_actions();
+        self.held.clear();
+        self.held_actions.clear();
+        actions
+            .into_iter()
+            .map(|action| ActionEvent {
+                action,
+                pressed: false,
+            })
+            .collect()
+    }
+}
+
+/// Bindings in `defaults` that are also the defaults of an action in `actions`, along with that
+/// action, leaving out actions `action` is meant to stack with
+fn clashes(
+    actions: &[(&'static str, Vec<Binding>)],
+    action: &str,
+    defaults: &[Binding],
+) -> Vec<(Binding, &'static str)> {
+    let stacks = |other: &str| {
+        STACKING
+            .iter()
+            .any(|group| group.contains(&action) && group.contains(&other))
+    };
+    actions
+        .iter()
+        .filter(|(other, _)| !stacks(other))
+        .flat_map(|(other, other_defaults)| {
+            defaults
+                .iter()
+                .filter(|binding| other_defaults.contains(binding))
+                .map(|binding| (*binding, *other))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_bindings_replace_defaults() {
+        let config = "{\"move_forward\": [Key(Z)], \"jump\": [Key(J)]}";
+        let mut input = InputMap::new(ron::from_str(config).unwrap());
+        input.register(MOVE_FORWARD, &[Binding::Key(VirtualKeyCode::W)]);
+        input.register(MOVE_BACKWARD, &[Binding::Key(VirtualKeyCode::S)]);
+
+        let w = Binding::Key(VirtualKeyCode::W);
+        let z = Binding::Key(VirtualKeyCode::Z);
+        assert!(input.process(w, ElementState::Pressed).is_empty());
+        assert_eq!(
+            input.process(z, ElementState::Pressed),
+            [ActionEvent {
+                action: MOVE_FORWARD,
+                pressed: true
+            }]
+        );
+        // Repeats are ignored
+        assert!(input.process(z, ElementState::Pressed).is_empty());
+        assert_eq!(input.held_actions(), [MOVE_FORWARD]);
+        assert_eq!(input.release_all().len(), 1);
+        assert!(input.held_actions().is_empty());
+
+        assert_eq!(input.unknown_actions().collect::<Vec<_>>(), ["jump"]);
+    }
+
+    #[test]
+    fn actions_stop_when_their_last_binding_is_released() {
+        let mut input = InputMap::new(BTreeMap::new());
+        let w = Binding::Key(VirtualKeyCode::W);
+        let up = Binding::Key(VirtualKeyCode::Up);
+        input.register(MOVE_FORWARD, &[w, up]);
+        let event = |pressed| {
+            vec![ActionEvent {
+                action: MOVE_FORWARD,
+                pressed,
+            }]
+        };
+
+        assert_eq!(input.process(w, ElementState::Pressed), event(true));
+        assert!(input.process(up, ElementState::Pressed).is_empty());
+        assert!(input.process(w, ElementState::Released).is_empty());
+        assert_eq!(input.held_actions(), [MOVE_FORWARD]);
+        assert_eq!(input.process(up, ElementState::Released), event(false));
+        assert!(input.held_actions().is_empty());
+        // Releasing something that was never pressed does nothing
+        assert!(input.process(up, ElementState::Released).is_empty());
+
+        input.process(w, ElementState::Pressed);
+        input.process(up, ElementState::Pressed);
+        assert_eq!(input.release_all(), event(false));
+        assert_eq!(input.process(w, ElementState::Pressed), event(true));
+    }
+
+    #[test]
+    fn shared_defaults_clash_unless_they_stack() {
+        let mut input = InputMap::new(BTreeMap::new());
+        let left = Binding::Mouse(MouseButton::Left);
+        let control = Binding::Key(VirtualKeyCode::LControl);
+        input.register(LOOK, &[left]);
+        input.register(ORBIT, &[left]);
+        input.register(SPRINT, &[control]);
+        input.register(STORE_BOOKMARK, &[control]);
+        assert_eq!(
+            input.clashing_defaults(),
+            [(SPRINT, STORE_BOOKMARK, control)]
+        );
+    }
+}
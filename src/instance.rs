@@ -1,19 +1,3 @@
-pub struct Instance {
-    pub position: cgmath::Vector3<f32>,
-    pub rotation: cgmath::Quaternion<f32>,
-}
-
-impl Instance {
-    pub fn to_raw(&self) -> InstanceRaw {
-        let model =
-            cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation);
-        InstanceRaw {
-            model: model.into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
@@ -22,6 +6,21 @@ pub struct InstanceRaw {
 }
 
 impl InstanceRaw {
+    pub fn new(model: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::{Matrix, SquareMatrix};
+
+        // Normals need the inverse transpose to stay perpendicular under non-uniform scale
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear
+            .invert()
+            .map_or(linear, |inverse| inverse.transpose());
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -6,10 +6,9 @@ pub struct LightUniform {
     pub position: [f32; 3],
     _padding1: u32,
     pub colour: [f32; 3],
-    _padding2: u32,
     pub strength: f32,
     pub radius: f32,
-    _padding3: [u32; 2],
+    _padding2: [u32; 3],
 }
 
 impl LightUniform {
@@ -18,24 +17,25 @@ impl LightUniform {
             position: [0.0; 3],
             _padding1: 0,
             colour: [0.0; 3],
-            _padding2: 0,
             strength: 0.0,
             radius: 0.0,
-            _padding3: [0; 2],
+            _padding2: [0; 3],
         }
     }
 
-    pub fn update_values(&mut self, light: &Light) {
-        self.position = light.position.into();
+    pub fn update_values(&mut self, light: &Light, position: Point3<f32>) {
+        self.position = position.into();
         self.colour = light.colour;
         self.strength = light.strength;
         self.radius = light.radius;
     }
 }
 
+/// A point light. Its position comes from the scene node holding it.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Light {
-    pub position: Point3<f32>,
     pub colour: [f32; 3],
     pub strength: f32,
+    /// Distance at which the light has fallen off to half its strength
     pub radius: f32,
 }
@@ -1,14 +1,34 @@
 mod state;
+use context::RenderContext;
 use state::State;
 
+mod bindings;
+mod bookmarks;
 mod camera;
+mod camera_controller;
+mod camera_path;
 mod instance;
 mod texture;
 
+mod autofocus;
+mod capture;
+mod config;
 mod context;
+mod headless;
+#[cfg(test)]
+mod golden;
+mod input;
 mod light;
 mod model;
 mod resources;
+mod scene;
+mod scene_file;
+mod sdf;
+mod preprocessor;
+mod recording;
+mod shaders;
+mod uniforms;
+mod watcher;
 
 use winit::{
     event::*,
@@ -18,36 +38,51 @@ use winit::{
 
 fn main() {
     env_logger::init();
+    // flashbang [--dev] [--config flashbang.ron] [scene.ron]
+    // flashbang render [scene.ron] --output frame.png|frame.exr [options]
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("render") {
+        args.next();
+        if let Err(e) = render(args) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let (scene_path, dev_mode, config) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut state = pollster::block_on(State::new(&window));
+    let context =
+        pollster::block_on(RenderContext::create(&window, wgpu::Backends::all())).unwrap();
+    let mut state = pollster::block_on(State::new(context, scene_path, dev_mode)).unwrap();
+    state.set_bindings(config.bindings);
+    state.set_bookmark_transition(config.bookmark_transition);
     let mut last_render_time = std::time::Instant::now();
-    let mut focused = true;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         state.input(&event);
+        if state.quit_requested() {
+            *control_flow = ControlFlow::Exit;
+        }
         match event {
             Event::WindowEvent { event, window_id } if window_id == window.id() => {
                 match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::Resized(physical_size) => {
                         state.resize(physical_size);
                     }
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                         state.resize(*new_inner_size);
                     }
-                    WindowEvent::Focused(focus) => focused = focus,
                     _ => {}
                 }
             }
@@ -75,3 +110,187 @@ fn main() {
         }
     });
 }
+
+/// Reads the interactive app's arguments, returning the scene path, whether it's in development
+/// mode, and the config
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> anyhow::Result<(Option<std::path::PathBuf>, bool, config::Config)> {
+    use anyhow::Context;
+
+    let mut scene_path = None;
+    let mut dev_mode = false;
+    let mut config_path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--dev" {
+            dev_mode = true;
+        } else if arg == "--config" {
+            config_path = Some(args.next().context("--config needs a value")?);
+        } else {
+            scene_path = Some(std::path::PathBuf::from(arg));
+        }
+    }
+
+    let config = match config_path {
+        Some(path) => config::Config::load(path)?,
+        None => config::Config::load_default()?,
+    };
+    Ok((scene_path, dev_mode, config))
+}
+
+const RENDER_USAGE: &str = "\
+usage: flashbang render [scene.ron] --output frame.png|frame.exr [options]
+       flashbang render [scene.ron] --output frames/####.png|video.y4m --frames <n> [options]
+
+options:
+    -o, --output <path>     image to write, as PNG or EXR by its extension. When recording, an
+                            image sequence with the #s replaced by frame numbers, or a Y4M video
+    --size <width>x<height> resolution, 800x600 by default
+    --frames <n>            record this many frames, advancing by a fixed timestep each frame
+    --fps <rate>            frame rate to record at, 30 by default
+    --camera <x,y,z>        camera position, replacing the scene file's
+    --look-at <x,y,z>       point the camera faces
+    --camera-track <name>   play one of the scene's camera tracks, recording all of it unless
+                            --frames is given
+    --bookmark <n>          view from one of the bookmarks saved beside the scene
+    --projection <mode>     perspective, orthographic, fisheye or equirectangular, replacing the
+                            scene file's. Equirectangular panoramas want a 2:1 --size
+    --aperture <size>       lens aperture for depth of field, replacing the scene file's lens.
+                            Raise --samples for the blur to smooth out
+    --focus <distance>      distance the lens focuses at, rather than what's in the middle
+    --stereo <mode>         off, sidebyside or anaglyph, replacing the scene file's stereo mode
+    --interocular <distance>
+                            distance between the stereo eyes
+    --convergence <distance>
+                            distance the stereo eyes' views meet at, which looks to be the
+                            depth of the screen
+    --samples <n>           frames to render before saving, 1 by default
+    --software              render on a software adapter";
+
+/// Renders one image, or records a sequence of frames, from the command line without opening a
+/// window
+fn render(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let mut scene_path = None;
+    let mut output = None;
+    let mut options = headless::HeadlessOptions::default();
+    let mut frames = None;
+    let mut fps = 30;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} needs a value\n\n{}", arg, RENDER_USAGE))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(std::path::PathBuf::from(value()?)),
+            "--size" => {
+                let size = value()?;
+                let (width, height) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .with_context(|| {
+                        format!("Expected --size <width>x<height>, found {:?}", size)
+                    })?;
+                options.width = width;
+                options.height = height;
+            }
+            "--camera" => options.camera_position = Some(parse_point(&value()?)?),
+            "--look-at" => options.camera_target = Some(parse_point(&value()?)?),
+            "--camera-track" => options.camera_track = Some(value()?),
+            "--bookmark" => {
+                let text = value()?;
+                let slot = text.parse().ok().filter(|&slot| slot < 10);
+                options.bookmark = Some(slot.with_context(|| {
+                    format!("Expected a bookmark from 0 to 9, found {:?}", text)
+                })?);
+            }
+            "--projection" => options.projection = Some(parse_projection(&value()?)?),
+            "--aperture" => options.aperture = Some(parse_distance(&arg, &value()?)?),
+            "--focus" => {
+                options.focus_distance = Some(parse_positive_distance(&arg, &value()?)?)
+            }
+            "--stereo" => options.stereo = Some(parse_stereo(&value()?)?),
+            "--interocular" => options.interocular = Some(parse_distance(&arg, &value()?)?),
+            "--convergence" => {
+                options.convergence = Some(parse_positive_distance(&arg, &value()?)?)
+            }
+            "--samples" => options.samples = parse_positive(&arg, &value()?)?,
+            "--frames" => frames = Some(parse_positive(&arg, &value()?)?),
+            "--fps" => fps = parse_positive(&arg, &value()?)?,
+            "--software" => options.force_fallback_adapter = true,
+            "-h" | "--help" => {
+                println!("{}", RENDER_USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => {
+                anyhow::bail!("Unknown option {}\n\n{}", arg, RENDER_USAGE)
+            }
+            _ => scene_path = Some(std::path::PathBuf::from(arg)),
+        }
+    }
+    let output = output.with_context(|| format!("No --output given\n\n{}", RENDER_USAGE))?;
+
+    // Y4M files are always recordings, even of a single frame
+    if frames.is_some() || options.camera_track.is_some() || recording::is_y4m(&output) {
+        let writer = recording::FrameWriter::create(&output, fps)?;
+        options.format = writer.format();
+        return headless::record(scene_path, &options, frames, fps, writer);
+    }
+    options.format = capture::format_for(&output);
+    let image = headless::render_image(scene_path, &options)?;
+    capture::save(&image, &output)
+}
+
+fn parse_positive(option: &str, text: &str) -> anyhow::Result<u32> {
+    match text.parse() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => anyhow::bail!("Expected a positive {}, found {:?}", option, text),
+    }
+}
+
+fn parse_distance(option: &str, text: &str) -> anyhow::Result<f32> {
+    match text.parse() {
+        Ok(value) if value >= 0.0 => Ok(value),
+        _ => anyhow::bail!("Expected a distance for {}, found {:?}", option, text),
+    }
+}
+
+fn parse_positive_distance(option: &str, text: &str) -> anyhow::Result<f32> {
+    match text.parse() {
+        Ok(value) if value > 0.0 => Ok(value),
+        _ => anyhow::bail!("Expected a positive distance for {}, found {:?}", option, text),
+    }
+}
+
+fn parse_stereo(text: &str) -> anyhow::Result<camera::StereoMode> {
+    use anyhow::Context;
+
+    camera::StereoMode::ALL
+        .into_iter()
+        .find(|mode| format!("{:?}", mode).eq_ignore_ascii_case(text))
+        .with_context(|| format!("Unknown stereo mode {:?}\n\n{}", text, RENDER_USAGE))
+}
+
+fn parse_projection(text: &str) -> anyhow::Result<camera::ProjectionMode> {
+    use anyhow::Context;
+
+    camera::ProjectionMode::ALL
+        .into_iter()
+        .find(|mode| format!("{:?}", mode).eq_ignore_ascii_case(text))
+        .with_context(|| format!("Unknown projection {:?}\n\n{}", text, RENDER_USAGE))
+}
+
+fn parse_point(text: &str) -> anyhow::Result<cgmath::Point3<f32>> {
+    let coordinates = text
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|c| c.len() == 3);
+    match coordinates {
+        Some(c) => Ok(cgmath::Point3::new(c[0], c[1], c[2])),
+        None => anyhow::bail!("Expected a point as x,y,z, found {:?}", text),
+    }
+}
@@ -1,5 +1,8 @@
 use std::ops::Range;
 
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
 use crate::texture;
 
 pub trait Vertex {
@@ -12,6 +15,8 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl Vertex for ModelVertex {
@@ -36,6 +41,16 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -44,14 +59,199 @@ impl Vertex for ModelVertex {
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub nodes: Vec<Node>,
+    pub root_nodes: Vec<usize>,
+}
+
+impl Model {
+    /// Every mesh referenced by the node hierarchy, paired with the world transform of its node
+    pub fn mesh_transforms(&self) -> Vec<(usize, cgmath::Matrix4<f32>)> {
+        let mut mesh_transforms = Vec::new();
+        let mut stack = self
+            .root_nodes
+            .iter()
+            .map(|&node| (node, cgmath::Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((index, parent_transform)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent_transform * node.transform;
+            mesh_transforms.extend(node.meshes.iter().map(|&mesh| (mesh, transform)));
+            stack.extend(node.children.iter().map(|&child| (child, transform)));
+        }
+        mesh_transforms
+    }
+}
+
+/// A node in a model's transform hierarchy. OBJ files produce one root node per object.
+pub struct Node {
+    pub name: String,
+    /// Transform relative to the parent node
+    pub transform: cgmath::Matrix4<f32>,
+    pub children: Vec<usize>,
+    pub meshes: Vec<usize>,
+}
+
+/// Constant material factors, following the MTL field names
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MaterialProperties {
+    /// Not used by the lighting, which has a fixed ambient term
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emissive: [f32; 3],
+    pub shininess: f32,
+    /// Opacity. Not used yet, since meshes are drawn opaque.
+    pub dissolve: f32,
+}
+
+impl Default for MaterialProperties {
+    fn default() -> Self {
+        Self {
+            ambient: [1.0; 3],
+            diffuse: [1.0; 3],
+            specular: [0.0; 3],
+            emissive: [0.0; 3],
+            shininess: 1.0,
+            dissolve: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub ambient: [f32; 3],
+    pub shininess: f32,
+    pub diffuse: [f32; 3],
+    pub dissolve: f32,
+    pub specular: [f32; 3],
+    _padding1: u32,
+    pub emissive: [f32; 3],
+    _padding2: u32,
+}
+
+impl From<&MaterialProperties> for MaterialUniform {
+    fn from(properties: &MaterialProperties) -> Self {
+        Self {
+            ambient: properties.ambient,
+            shininess: properties.shininess,
+            diffuse: properties.diffuse,
+            dissolve: properties.dissolve,
+            specular: properties.specular,
+            _padding1: 0,
+            emissive: properties.emissive,
+            _padding2: 0,
+        }
+    }
+}
+
+/// Texture maps for a material. Maps the source file doesn't provide should be filled with
+/// neutral 1x1 textures so that every material binds the same layout.
+pub struct MaterialTextures {
+    pub diffuse: texture::Texture,
+    pub specular: texture::Texture,
+    pub normal: texture::Texture,
+    pub emissive: texture::Texture,
 }
 
 pub struct Material {
     pub name: String,
+    pub properties: MaterialProperties,
     pub diffuse_texture: texture::Texture,
+    pub specular_texture: texture::Texture,
+    pub normal_texture: texture::Texture,
+    pub emissive_texture: texture::Texture,
+    pub uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: String,
+        properties: MaterialProperties,
+        textures: MaterialTextures,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&properties)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let MaterialTextures {
+            diffuse,
+            specular,
+            normal,
+            emissive,
+        } = textures;
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&specular.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&specular.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&emissive.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(&format!("{:?} Material Bind Group", name)),
+        });
+
+        Self {
+            name,
+            properties,
+            diffuse_texture: diffuse,
+            specular_texture: specular,
+            normal_texture: normal,
+            emissive_texture: emissive,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn set_properties(&mut self, queue: &wgpu::Queue, properties: MaterialProperties) {
+        self.properties = properties;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(&properties)]),
+        );
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
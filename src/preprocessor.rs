@@ -0,0 +1,359 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// Expands preprocessor directives in WGSL source:
+///
+/// - `#include "file.wgsl"` pastes in another file, loaded with `load`. Each file is only
+///   included once, so shared files can include whatever they depend on.
+/// - `#define NAME value` replaces later uses of `NAME` with `value`. The value can be left out
+///   to define a name just for `#ifdef`. `#undef NAME` removes a definition.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the lines between them.
+///
+/// Directives must be on their own line. Errors give the file and line they came from.
+pub fn preprocess<F>(name: &str, source: &str, load: F) -> anyhow::Result<Preprocessed>
+where
+    F: Fn(&str) -> anyhow::Result<Cow<'static, str>>,
+{
+    let mut preprocessor = Preprocessor {
+        load,
+        defines: HashMap::new(),
+        included: HashSet::from([name.to_string()]),
+        output: Preprocessed::default(),
+    };
+    preprocessor.process(name, source)?;
+    Ok(preprocessor.output)
+}
+
+/// Expanded source, remembering where each of its lines came from
+#[derive(Debug, Default)]
+pub struct Preprocessed {
+    pub source: String,
+    /// File name and line number of each line of `source`
+    origins: Vec<(String, usize)>,
+}
+
+impl Preprocessed {
+    /// File name and line number that line `line` of the expanded source came from, counting
+    /// from 1
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let (file, line) = self.origins.get(line.checked_sub(1)?)?;
+        Some((file, *line))
+    }
+
+    /// Points the `name:line:column` locations and numbered source lines in naga's diagnostics,
+    /// which count lines of the expanded source, at the file and line each came from instead
+    pub fn locate_errors(&self, message: &str) -> String {
+        message
+            .lines()
+            .map(|line| {
+                self.locate(line)
+                    .or_else(|| self.renumber(line))
+                    .unwrap_or_else(|| line.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Rewrites a `┌─ name:line:column` location
+    fn locate(&self, line: &str) -> Option<String> {
+        let (before, location) = line.split_once("┌─ ")?;
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?;
+        let number = parts.next()?.parse().ok()?;
+        parts.next()?;
+        let (file, original) = self.origin(number)?;
+        Some(format!("{}┌─ {}:{}:{}", before, file, original, column))
+    }
+
+    /// Rewrites the number in the gutter of a quoted source line, like `170 │ let x = 1;`
+    fn renumber(&self, line: &str) -> Option<String> {
+        let (gutter, code) = line.split_once(" │")?;
+        let number = gutter.trim_start().parse().ok()?;
+        let (_, original) = self.origin(number)?;
+        Some(format!(
+            "{:>width$} │{}",
+            original,
+            code,
+            width = gutter.len()
+        ))
+    }
+}
+
+struct Preprocessor<F> {
+    load: F,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    output: Preprocessed,
+}
+
+struct Conditional {
+    /// Whether lines in the current branch are kept
+    active: bool,
+    /// Whether the enclosing block was active, which an `#else` can't override
+    parent_active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+impl<F> Preprocessor<F>
+where
+    F: Fn(&str) -> anyhow::Result<Cow<'static, str>>,
+{
+    fn process(&mut self, name: &str, source: &str) -> anyhow::Result<()> {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        let line = self.substitute(line);
+                        self.output.source.push_str(&line);
+                        self.output.source.push('\n');
+                        self.output.origins.push((name.to_string(), line_number));
+                    }
+                    continue;
+                }
+            };
+
+            let (keyword, rest) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            let rest = rest.trim();
+            let error = |message: String| anyhow::anyhow!("{}:{}: {}", name, line_number, message);
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains_key(identifier(rest).map_err(error)?);
+                    conditionals.push(Conditional {
+                        active: active && defined == (keyword == "ifdef"),
+                        parent_active: active,
+                        seen_else: false,
+                        line: line_number,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals
+                        .last_mut()
+                        .filter(|c| !c.seen_else)
+                        .ok_or_else(|| error("#else without a matching #ifdef".to_string()))?;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                    conditional.seen_else = true;
+                }
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| error("#endif without a matching #ifdef".to_string()))?;
+                }
+                _ if !active => {}
+                "include" => {
+                    let file = rest
+                        .strip_prefix('"')
+                        .and_then(|rest| rest.strip_suffix('"'))
+                        .ok_or_else(|| {
+                            error(format!("Expected a quoted file name, found {:?}", rest))
+                        })?;
+                    if self.included.insert(file.to_string()) {
+                        let included = (self.load)(file)
+                            .map_err(|e| error(format!("Couldn't include {:?}: {:?}", file, e)))?;
+                        self.process(file, &included)?;
+                    }
+                }
+                "define" => {
+                    let (define, value) =
+                        rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    let define = identifier(define).map_err(error)?;
+                    let value = self.substitute(value.trim());
+                    self.defines.insert(define.to_string(), value);
+                }
+                "undef" => {
+                    self.defines.remove(identifier(rest).map_err(error)?);
+                }
+                _ => return Err(error(format!("Unknown directive #{}", keyword))),
+            }
+        }
+
+        match conditionals.last() {
+            Some(conditional) => anyhow::bail!(
+                "{}:{}: #ifdef without a matching #endif",
+                name,
+                conditional.line
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces defined names with their values, matching whole identifiers only
+    fn substitute(&self, line: &str) -> String {
+        let mut output = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find(is_identifier_char) {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+            let word = &rest[..end];
+            match self.defines.get(word) {
+                Some(value) if !value.is_empty() => output.push_str(value),
+                _ => output.push_str(word),
+            }
+            rest = &rest[end..];
+        }
+        output.push_str(rest);
+        output
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn identifier(text: &str) -> Result<&str, String> {
+    if !text.is_empty() && text.chars().all(is_identifier_char) {
+        Ok(text)
+    } else {
+        Err(format!("Expected a name, found {:?}", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads includes from `files`, a list of names and sources
+    fn run(source: &str, files: &[(&str, &'static str)]) -> anyhow::Result<Preprocessed> {
+        let files = files.to_vec();
+        preprocess("main.wgsl", source, move |name| {
+            files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map(|(_, source)| Cow::Borrowed(*source))
+                .ok_or_else(|| anyhow::anyhow!("No file called {:?}", name))
+        })
+    }
+
+    fn expand(source: &str) -> String {
+        run(source, &[]).unwrap().source
+    }
+
+    fn error(source: &str) -> String {
+        run(source, &[]).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let source = "\
+#define A
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#ifdef A
+dropped with its parent
+#endif
+#endif
+#ifndef A
+dropped
+#else
+else of ifndef
+#endif
+";
+        assert_eq!(expand(source), "a\nnot b\nelse of ifndef\n");
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let source = "\
+#define SIZE 4
+#define DOUBLE SIZE * 2u
+#define FLAG
+let size = SIZE + SIZED + MY_SIZE + size.SIZE;
+let double = DOUBLE;
+let flag = FLAG;
+";
+        assert_eq!(
+            expand(source),
+            "let size = 4 + SIZED + MY_SIZE + size.4;\nlet double = 4 * 2u;\nlet flag = FLAG;\n"
+        );
+    }
+
+    #[test]
+    fn undef_removes_a_define() {
+        let source = "\
+#define A 1
+A
+#undef A
+A
+#ifdef A
+dropped
+#endif
+";
+        assert_eq!(expand(source), "1\nA\n");
+    }
+
+    #[test]
+    fn files_are_only_included_once() {
+        let files = [
+            ("a.wgsl", "#include \"common.wgsl\"\na\n"),
+            ("common.wgsl", "common\n"),
+        ];
+        let source = "\
+#include \"common.wgsl\"
+#include \"a.wgsl\"
+#include \"main.wgsl\"
+main
+";
+        let expanded = run(source, &files).unwrap();
+        assert_eq!(expanded.source, "common\na\nmain\n");
+    }
+
+    #[test]
+    fn lines_are_traced_back_to_their_files() {
+        let files = [("common.wgsl", "// common\nfn common() {}\n")];
+        let source = "\
+#define A
+#include \"common.wgsl\"
+#ifdef A
+fn main() {}
+#endif
+";
+        let expanded = run(source, &files).unwrap();
+        assert_eq!(expanded.origin(1), Some(("common.wgsl", 1)));
+        assert_eq!(expanded.origin(3), Some(("main.wgsl", 4)));
+        assert_eq!(expanded.origin(4), None);
+
+        let message = "\
+error: unknown type
+  ┌─ wgsl:3:4
+  │
+3 │ fn main() {}
+  │    ^^^^ here";
+        assert_eq!(
+            expanded.locate_errors(message),
+            "\
+error: unknown type
+  ┌─ main.wgsl:4:4
+  │
+4 │ fn main() {}
+  │    ^^^^ here"
+        );
+    }
+
+    #[test]
+    fn errors_give_the_file_and_line() {
+        assert!(error("a\n#ifdef A\nb\n").starts_with("main.wgsl:2: "));
+        assert!(error("#ifdef A\n#else\n#else\n#endif\n").starts_with("main.wgsl:3: "));
+        assert!(error("a\n#else\n").starts_with("main.wgsl:2: "));
+        assert!(error("#endif\n").starts_with("main.wgsl:1: "));
+        assert!(error("a\nb\n#pragma once\n").starts_with("main.wgsl:3: "));
+        assert!(error("#include \"missing.wgsl\"\n").starts_with("main.wgsl:1: "));
+
+        let files = [("bad.wgsl", "a\n#endif\n")];
+        let included = run("#include \"bad.wgsl\"\n", &files).unwrap_err();
+        assert!(included.to_string().starts_with("bad.wgsl:2: "));
+    }
+}
@@ -0,0 +1,187 @@
+//! Writing rendered frames out as numbered image sequences or Y4M video.
+//!
+//! Sequences name each frame by replacing the run of `#`s in the file name with the frame number,
+//! so `frames/turntable_####.png` is written as `frames/turntable_0000.png` onwards. Names without
+//! any `#`s get `_0000` added before the extension. EXR sequences keep the float frames.
+//!
+//! Y4M files hold uncompressed 4:4:4 frames, converted with BT.601 limited range coefficients
+//! since the format can't say which it uses. Most tools read them directly, so
+//! `ffmpeg -i out.y4m out.mp4` turns one into a video.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::capture;
+
+pub enum FrameWriter {
+    Sequence {
+        pattern: PathBuf,
+        next: u32,
+    },
+    Y4m {
+        path: PathBuf,
+        writer: BufWriter<File>,
+        fps: u32,
+        /// Set by the first frame, which every later one has to match
+        size: Option<(u32, u32)>,
+    },
+}
+
+impl FrameWriter {
+    /// Writes a Y4M file if `path` ends in `.y4m`, and a sequence of the images it names otherwise
+    pub fn create(path: &Path, fps: u32) -> anyhow::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Couldn't create directory {:?}", parent))?;
+        }
+        if is_y4m(path) {
+            let file = File::create(path).with_context(|| format!("Couldn't create {:?}", path))?;
+            Ok(Self::Y4m {
+                path: path.to_path_buf(),
+                writer: BufWriter::new(file),
+                fps,
+                size: None,
+            })
+        } else {
+            Ok(Self::Sequence {
+                pattern: path.to_path_buf(),
+                next: 0,
+            })
+        }
+    }
+
+    /// Target format to render frames for this writer in
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            Self::Sequence { pattern, .. } => capture::format_for(pattern),
+            Self::Y4m { .. } => wgpu::TextureFormat::Rgba8UnormSrgb,
+        }
+    }
+
+    pub fn write(&mut self, image: &image::DynamicImage) -> anyhow::Result<()> {
+        match self {
+            Self::Sequence { pattern, next } => {
+                capture::save(image, &frame_path(pattern, *next))?;
+                *next += 1;
+                Ok(())
+            }
+            Self::Y4m {
+                path,
+                writer,
+                fps,
+                size,
+            } => {
+                let image = image.to_rgba8();
+                let dimensions = image.dimensions();
+                match *size {
+                    None => {
+                        let (width, height) = dimensions;
+                        writeln!(
+                            writer,
+                            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                            width, height, fps
+                        )?;
+                        *size = Some(dimensions);
+                    }
+                    Some(size) => anyhow::ensure!(
+                        size == dimensions,
+                        "Frames in {:?} are {:?}, but this one is {:?}",
+                        path,
+                        size,
+                        dimensions
+                    ),
+                }
+                write_y4m_frame(writer, &image)
+                    .with_context(|| format!("Couldn't write to {:?}", path))
+            }
+        }
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Sequence { .. } => Ok(()),
+            Self::Y4m {
+                path, mut writer, ..
+            } => writer
+                .flush()
+                .with_context(|| format!("Couldn't write to {:?}", path)),
+        }
+    }
+}
+
+pub fn is_y4m(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("y4m"))
+}
+
+/// Path of frame `index` in the sequence named by `pattern`
+pub fn frame_path(pattern: &Path, index: u32) -> PathBuf {
+    let stem = pattern
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let stem = match stem.find('#') {
+        Some(start) => {
+            let width = stem[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{:0width$}{}",
+                &stem[..start],
+                index,
+                &stem[start + width..],
+                width = width
+            )
+        }
+        None => format!("{}_{:04}", stem, index),
+    };
+    let mut path = pattern.with_file_name(stem);
+    if let Some(extension) = pattern.extension() {
+        path.set_extension(extension);
+    }
+    path
+}
+
+fn write_y4m_frame(writer: &mut impl Write, image: &image::RgbaImage) -> std::io::Result<()> {
+    let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+    for pixel in image.pixels() {
+        let [r, g, b, _] = pixel.0.map(|c| c as f32 / 255.0);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = (b - y) / 1.772;
+        let cr = (r - y) / 1.402;
+        planes[0].push((16.0 + 219.0 * y).round() as u8);
+        planes[1].push((128.0 + 224.0 * cb).round() as u8);
+        planes[2].push((128.0 + 224.0 * cr).round() as u8);
+    }
+    writer.write_all(b"FRAME\n")?;
+    for plane in &planes {
+        writer.write_all(plane)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_paths_are_numbered() {
+        assert_eq!(
+            frame_path(Path::new("frames/turntable_####.png"), 7),
+            Path::new("frames/turntable_0007.png")
+        );
+        assert_eq!(
+            frame_path(Path::new("##-frame.exr"), 123),
+            Path::new("123-frame.exr")
+        );
+        assert_eq!(
+            frame_path(Path::new("out.png"), 2),
+            Path::new("out_0002.png")
+        );
+    }
+}
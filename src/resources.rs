@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::io::{BufReader, Cursor};
 
+use anyhow::Context;
 use cfg_if::cfg_if;
+use cgmath::SquareMatrix;
 use wgpu::util::DeviceExt;
 
 use crate::{model, texture};
@@ -59,19 +62,166 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
 
 pub async fn load_texture(
     file_name: &str,
+    is_normal_map: bool,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     log::info!("Loading {}...", file_name);
     let data = load_binary(file_name).await?;
     log::info!("Creating texture...");
-    let result = texture::Texture::from_bytes(device, queue, &data, file_name);
+    let result = texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map);
     log::info!("Finished loading {}", file_name);
     result
 }
 
+/// Loads `file_name`, or creates a 1x1 texture of `default` when no file is given
+pub async fn load_texture_or(
+    file_name: &str,
+    default: [u8; 4],
+    is_normal_map: bool,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    if file_name.is_empty() {
+        texture::Texture::from_colour(device, queue, default, "default_texture", is_normal_map)
+    } else {
+        load_texture(file_name, is_normal_map, device, queue).await
+    }
+}
+
+/// Parses a colour stored as three whitespace separated floats, e.g. `Ke 1.0 0.5 0.0`
+fn parse_colour(value: &str) -> anyhow::Result<[f32; 3]> {
+    let components = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<f32>, _>>()?;
+    match components[..] {
+        [r, g, b] => Ok([r, g, b]),
+        _ => anyhow::bail!("Expected 3 colour components, found {:?}", value),
+    }
+}
+
+/// How normals are generated for meshes that don't provide any
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NormalGeneration {
+    /// Average the normals of the faces around each position
+    Smooth,
+    /// Give every face its own normal, splitting up shared vertices
+    Faceted,
+}
+
+/// Fills in the normals of an indexed triangle list. Faceted normals can't share vertices between
+/// faces, so in that case every index gets its own vertex and the indices are rewritten.
+fn generate_normals(
+    vertices: &mut Vec<model::ModelVertex>,
+    indices: &mut Vec<u32>,
+    mode: NormalGeneration,
+) {
+    use cgmath::{InnerSpace, Vector3};
+
+    if mode == NormalGeneration::Faceted {
+        *vertices = indices.iter().map(|&i| vertices[i as usize]).collect();
+        *indices = (0..vertices.len() as u32).collect();
+    }
+
+    // Unnormalised so that larger faces contribute more to smooth normals
+    let face_normal = |vertices: &[model::ModelVertex], triangle: &[u32]| {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        (b - a).cross(c - a)
+    };
+    // Degenerate faces have no direction, so point them up rather than producing NaNs
+    let normalize = |normal: Vector3<f32>| {
+        if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    };
+
+    match mode {
+        NormalGeneration::Faceted => {
+            for triangle in indices.chunks_exact(3) {
+                let normal = normalize(face_normal(vertices, triangle));
+                for &i in triangle {
+                    vertices[i as usize].normal = normal.into();
+                }
+            }
+        }
+        NormalGeneration::Smooth => {
+            // Sum by position rather than by vertex, so UV seams don't show up as hard edges
+            let key = |vertex: &model::ModelVertex| vertex.position.map(f32::to_bits);
+            let mut sums = HashMap::<_, Vector3<f32>>::new();
+            for triangle in indices.chunks_exact(3) {
+                let normal = face_normal(vertices, triangle);
+                for &i in triangle {
+                    *sums
+                        .entry(key(&vertices[i as usize]))
+                        .or_insert(Vector3::new(0.0, 0.0, 0.0)) += normal;
+                }
+            }
+            for vertex in vertices.iter_mut() {
+                let sum = sums.get(&key(vertex)).copied().unwrap_or(Vector3::unit_y());
+                vertex.normal = normalize(sum).into();
+            }
+        }
+    }
+}
+
+/// Checks that an OBJ object's attributes, indices and material fit together, since tobj
+/// doesn't and anything out of range would panic while building its vertices
+fn check_obj_mesh(m: &tobj::Model, file_name: &str, material_count: usize) -> anyhow::Result<()> {
+    let vertex_count = m.mesh.positions.len() / 3;
+    let has_normals = !m.mesh.normals.is_empty();
+    let has_tex_coords = !m.mesh.texcoords.is_empty();
+    anyhow::ensure!(
+        !has_normals || m.mesh.normals.len() == vertex_count * 3,
+        "Object {:?} in {:?} has {} normals for {} vertices",
+        m.name,
+        file_name,
+        m.mesh.normals.len() / 3,
+        vertex_count
+    );
+    anyhow::ensure!(
+        !has_tex_coords || m.mesh.texcoords.len() == vertex_count * 2,
+        "Object {:?} in {:?} has {} texture coordinates for {} vertices",
+        m.name,
+        file_name,
+        m.mesh.texcoords.len() / 2,
+        vertex_count
+    );
+    anyhow::ensure!(
+        m.mesh.indices.len().is_multiple_of(3),
+        "Object {:?} in {:?} is not made of triangles",
+        m.name,
+        file_name
+    );
+    if let Some(index) = m.mesh.indices.iter().find(|&&i| i as usize >= vertex_count) {
+        anyhow::bail!(
+            "Object {:?} in {:?} references vertex {} but only has {}",
+            m.name,
+            file_name,
+            index,
+            vertex_count
+        );
+    }
+    if let Some(material) = m.mesh.material_id.filter(|&i| i >= material_count) {
+        anyhow::bail!(
+            "Object {:?} in {:?} uses material {} but only {} were loaded",
+            m.name,
+            file_name,
+            material,
+            material_count
+        );
+    }
+    Ok(())
+}
+
+/// Loads an OBJ model and its MTL materials, with a mesh for each object. The ambient colour
+/// (`Ka`) and dissolve (`d`) are read, but lighting uses a fixed ambient term and meshes are
+/// drawn opaque, so neither changes how the model looks.
 pub async fn load_model(
     file_name: &str,
+    normal_generation: NormalGeneration,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
@@ -88,76 +238,569 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            match load_string(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(e) => {
+                    // tobj can only report a generic error, so log the reason here
+                    log::error!("Failed to load material library {:?}: {}", p, e);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
         },
     )
-    .await?;
+    .await
+    .with_context(|| format!("Failed to parse {:?}", file_name))?;
+    let obj_materials = obj_materials
+        .with_context(|| format!("Failed to load the materials of {:?}", file_name))?;
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    for m in obj_materials {
+        // tobj has no fields for emission, so it ends up with the unrecognised parameters
+        let emissive = match m.unknown_param.get("Ke") {
+            Some(value) => parse_colour(value)?,
+            None => [0.0; 3],
+        };
+        let emissive_map = m.unknown_param.get("map_Ke").map_or("", String::as_str);
+
+        let properties = model::MaterialProperties {
+            ambient: m.ambient,
+            diffuse: m.diffuse,
+            specular: m.specular,
+            emissive,
+            shininess: m.shininess,
+            dissolve: m.dissolve,
+        };
+        // The maps are multiplied with the factors above, so missing ones default to white
+        let textures = model::MaterialTextures {
+            diffuse: load_texture_or(&m.diffuse_texture, [255; 4], false, device, queue).await?,
+            specular: load_texture_or(&m.specular_texture, [255; 4], false, device, queue).await?,
+            normal: load_texture_or(&m.normal_texture, [128, 128, 255, 255], true, device, queue)
+                .await?,
+            emissive: load_texture_or(emissive_map, [255; 4], false, device, queue).await?,
+        };
+
+        materials.push(model::Material::new(
+            device, layout, m.name, properties, textures,
+        ));
+    }
+
+    // Meshes without a material use index 0, so make sure there is always one to bind
+    if materials.is_empty() {
+        let textures = model::MaterialTextures {
+            diffuse: load_texture_or("", [255; 4], false, device, queue).await?,
+            specular: load_texture_or("", [255; 4], false, device, queue).await?,
+            normal: load_texture_or("", [128, 128, 255, 255], true, device, queue).await?,
+            emissive: load_texture_or("", [255; 4], false, device, queue).await?,
+        };
+        materials.push(model::Material::new(
+            device,
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+            "default".to_string(),
+            model::MaterialProperties::default(),
+            textures,
+        ));
+    }
 
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
+    let nodes = models
+        .iter()
+        .enumerate()
+        .map(|(i, m)| model::Node {
+            name: m.name.clone(),
+            transform: cgmath::Matrix4::identity(),
+            children: Vec::new(),
+            meshes: vec![i],
         })
-    }
+        .collect::<Vec<_>>();
+    let root_nodes = (0..nodes.len()).collect();
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            check_obj_mesh(&m, file_name, materials.len())?;
+            let vertex_count = m.mesh.positions.len() / 3;
+            let has_normals = !m.mesh.normals.is_empty();
+            let has_tex_coords = !m.mesh.texcoords.is_empty();
+
+            let mut vertices = (0..vertex_count)
                 .map(|i| model::ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                    // OBJ puts v = 0 at the bottom of the image, wgpu at the top
+                    tex_coords: if has_tex_coords {
+                        [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]]
+                    } else {
+                        [0.0; 2]
+                    },
+                    // Filled in by generate_normals below when missing
+                    normal: if has_normals {
+                        [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ]
+                    } else {
+                        [0.0; 3]
+                    },
+                    // Filled in by generate_tangents below
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
                 })
                 .collect::<Vec<_>>();
 
+            let mut indices = m.mesh.indices;
+            if !has_normals {
+                generate_normals(&mut vertices, &mut indices, normal_generation);
+            }
+
+            anyhow::ensure!(
+                bevy_mikktspace::generate_tangents(&mut TangentGeometry {
+                    vertices: &mut vertices,
+                    indices: &indices,
+                }),
+                "Failed to generate tangents for {:?} in {:?}",
+                m.name,
+                file_name
+            );
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
+                label: Some(&format!("{:?} Vertex Buffer", m.name)),
                 contents: bytemuck::cast_slice(&vertices),
                 usage: wgpu::BufferUsages::VERTEX,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                label: Some(&format!("{:?} Index Buffer", m.name)),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
-            model::Mesh {
-                name: file_name.to_string(),
+            Ok(model::Mesh {
+                name: m.name,
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        nodes,
+        root_nodes,
+    })
+}
+
+/// Loads a buffer or image referenced by a glTF file, either inline as a data URI or relative to
+/// the glTF file itself
+async fn load_gltf_uri(file_name: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .with_context(|| format!("Unsupported data URI in {:?}", file_name))?;
+        return Ok(base64::decode(encoded)?);
+    }
+
+    let path = match file_name.rsplit_once('/') {
+        Some((directory, _)) => format!("{}/{}", directory, uri),
+        None => uri.to_string(),
+    };
+    load_binary(&path).await
+}
+
+/// Loads a glTF or GLB model, with a mesh for each primitive. Materials keep their base colour,
+/// normal and emissive textures, but metallic-roughness and occlusion textures are ignored, so
+/// metalness and roughness only come from the material's factors and are the same across it.
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(&load_binary(file_name).await?)?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .take()
+                .with_context(|| format!("Missing binary chunk in {:?}", file_name))?,
+            gltf::buffer::Source::Uri(uri) => load_gltf_uri(file_name, uri).await?,
+        };
+        buffers.push(data);
+    }
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let data = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let buffer = &buffers[view.buffer().index()];
+                buffer[view.offset()..view.offset() + view.length()].to_vec()
             }
+            gltf::image::Source::Uri { uri, .. } => load_gltf_uri(file_name, uri).await?,
+        };
+        images.push(data);
+    }
+
+    let load_image = |texture: Option<gltf::texture::Texture>, default, is_normal_map| {
+        let label = format!("{:?} Texture", file_name);
+        match texture {
+            Some(texture) => {
+                let bytes = &images[texture.source().index()];
+                texture::Texture::from_bytes(device, queue, bytes, &label, is_normal_map)
+            }
+            None => texture::Texture::from_colour(device, queue, default, &label, is_normal_map),
+        }
+    };
+
+    let mut materials = Vec::new();
+    for m in document.materials() {
+        let pbr = m.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let metallic = pbr.metallic_factor();
+        let roughness = pbr.roughness_factor().max(0.01);
+
+        // Approximate the metallic and roughness factors with Blinn-Phong: dielectrics reflect ~4%
+        // and metals reflect their base colour, and roughness maps onto the matching specular
+        // exponent
+        let dielectric = 0.04 * (1.0 - metallic);
+        let properties = model::MaterialProperties {
+            ambient: [r, g, b],
+            diffuse: [
+                r * (1.0 - metallic),
+                g * (1.0 - metallic),
+                b * (1.0 - metallic),
+            ],
+            specular: [
+                dielectric + r * metallic,
+                dielectric + g * metallic,
+                dielectric + b * metallic,
+            ],
+            emissive: m.emissive_factor(),
+            shininess: 2.0 / roughness.powi(4) - 2.0,
+            dissolve: a,
+        };
+        let textures = model::MaterialTextures {
+            diffuse: load_image(
+                pbr.base_color_texture().map(|t| t.texture()),
+                [255; 4],
+                false,
+            )?,
+            specular: load_image(None, [255; 4], false)?,
+            normal: load_image(
+                m.normal_texture().map(|t| t.texture()),
+                [128, 128, 255, 255],
+                true,
+            )?,
+            emissive: load_image(m.emissive_texture().map(|t| t.texture()), [255; 4], false)?,
+        };
+
+        let name = m.name().unwrap_or("unnamed").to_string();
+        materials.push(model::Material::new(
+            device, layout, name, properties, textures,
+        ));
+    }
+
+    // Primitives without a material use the glTF default material, appended at the end
+    let default_material = materials.len();
+    materials.push(model::Material::new(
+        device,
+        layout,
+        "default".to_string(),
+        model::MaterialProperties::default(),
+        model::MaterialTextures {
+            diffuse: load_image(None, [255; 4], false)?,
+            specular: load_image(None, [255; 4], false)?,
+            normal: load_image(None, [128, 128, 255, 255], true)?,
+            emissive: load_image(None, [255; 4], false)?,
+        },
+    ));
+
+    let mut meshes = Vec::new();
+    // glTF meshes contain several primitives, each of which becomes one of our meshes
+    let mut gltf_meshes = Vec::new();
+    for mesh in document.meshes() {
+        let mesh_name = mesh.name().unwrap_or("unnamed");
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            anyhow::ensure!(
+                primitive.mode() == gltf::mesh::Mode::Triangles,
+                "Mesh {:?} in {:?} is not a triangle list",
+                mesh_name,
+                file_name
+            );
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions = reader
+                .read_positions()
+                .with_context(|| format!("Mesh {:?} has no positions", mesh_name))?;
+            let mut normals = reader.read_normals();
+            let mut tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32());
+            let mut vertices = positions
+                .map(|position| model::ModelVertex {
+                    position,
+                    tex_coords: tex_coords
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .unwrap_or([0.0; 2]),
+                    normal: normals
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .unwrap_or([0.0; 3]),
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
+                })
+                .collect::<Vec<_>>();
+
+            let mut indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            if let Some(index) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+                anyhow::bail!(
+                    "Mesh {:?} in {:?} references vertex {} but only has {}",
+                    mesh_name,
+                    file_name,
+                    index,
+                    vertices.len()
+                );
+            }
+
+            // The glTF spec asks for flat normals when a primitive doesn't have any, in which case
+            // any tangents it provides are to be ignored as well
+            let tangents = match normals {
+                Some(_) => reader.read_tangents(),
+                None => {
+                    generate_normals(&mut vertices, &mut indices, NormalGeneration::Faceted);
+                    None
+                }
+            };
+
+            match tangents {
+                Some(tangents) => {
+                    for (vertex, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+                        let normal = cgmath::Vector3::from(vertex.normal);
+                        let tangent = cgmath::Vector3::new(x, y, z);
+                        vertex.tangent = tangent.into();
+                        vertex.bitangent = (normal.cross(tangent) * w).into();
+                    }
+                }
+                None => anyhow::ensure!(
+                    bevy_mikktspace::generate_tangents(&mut TangentGeometry {
+                        vertices: &mut vertices,
+                        indices: &indices,
+                    }),
+                    "Failed to generate tangents for {:?} in {:?}",
+                    mesh_name,
+                    file_name
+                ),
+            }
+
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Vertex Buffer", mesh_name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Index Buffer", mesh_name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            primitives.push(meshes.len());
+            meshes.push(model::Mesh {
+                name: mesh_name.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                material: primitive.material().index().unwrap_or(default_material),
+            });
+        }
+        gltf_meshes.push(primitives);
+    }
+
+    let nodes = document
+        .nodes()
+        .map(|node| model::Node {
+            name: node.name().unwrap_or("unnamed").to_string(),
+            transform: node.transform().matrix().into(),
+            children: node.children().map(|child| child.index()).collect(),
+            meshes: node
+                .mesh()
+                .map(|mesh| gltf_meshes[mesh.index()].clone())
+                .unwrap_or_default(),
         })
-        .collect::<Vec<_>>();
+        .collect();
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .with_context(|| format!("No scenes in {:?}", file_name))?;
+    let root_nodes = scene.nodes().map(|node| node.index()).collect();
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        nodes,
+        root_nodes,
+    })
+}
 
-    Ok(model::Model { meshes, materials })
+/// Indexed triangle list adapter for MikkTSpace tangent generation
+struct TangentGeometry<'a> {
+    vertices: &'a mut [model::ModelVertex],
+    indices: &'a [u32],
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &model::ModelVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        // MikkTSpace expects v to point up the image, as normal maps are authored that way
+        let [u, v] = self.vertex(face, vert).tex_coords;
+        [u, 1.0 - v]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let index = self.indices[face * 3 + vert] as usize;
+        let vertex = &mut self.vertices[index];
+        let normal = cgmath::Vector3::from(vertex.normal);
+        let t = cgmath::Vector3::new(tangent[0], tangent[1], tangent[2]);
+        vertex.tangent = t.into();
+        vertex.bitangent = (normal.cross(t) * tangent[3]).into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Vector3};
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> model::ModelVertex {
+        model::ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0; 3],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+
+    fn assert_normal(vertex: &model::ModelVertex, expected: Vector3<f32>) {
+        let normal = Vector3::from(vertex.normal);
+        assert!(
+            (normal - expected).magnitude() < 1e-5,
+            "{:?} != {:?}",
+            normal,
+            expected
+        );
+    }
+
+    #[test]
+    fn smooth_normals_are_shared_across_uv_seams() {
+        // Two triangles folded along the x axis, whose shared edge is split by a UV seam
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+            vertex([0.0, 0.0, 0.0], [0.5, 0.5]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.5]),
+            vertex([0.0, 0.0, 1.0], [0.5, 1.0]),
+        ];
+        let mut indices = vec![0, 1, 2, 3, 4, 5];
+        generate_normals(&mut vertices, &mut indices, NormalGeneration::Smooth);
+
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+        let edge = Vector3::new(0.0, -1.0, 1.0).normalize();
+        for i in [0, 1, 3, 4] {
+            assert_normal(&vertices[i], edge);
+        }
+        assert_normal(&vertices[2], Vector3::unit_z());
+        assert_normal(&vertices[5], -Vector3::unit_y());
+    }
+
+    #[test]
+    fn faceted_normals_give_every_index_its_own_vertex() {
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+            vertex([0.0, 0.0, 1.0], [1.0, 1.0]),
+        ];
+        let mut indices = vec![0, 1, 2, 0, 1, 3];
+        generate_normals(&mut vertices, &mut indices, NormalGeneration::Faceted);
+
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(vertices[3].position, [0.0, 0.0, 0.0]);
+        for i in 0..3 {
+            assert_normal(&vertices[i], Vector3::unit_z());
+            assert_normal(&vertices[i + 3], -Vector3::unit_y());
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_point_up() {
+        for mode in [NormalGeneration::Smooth, NormalGeneration::Faceted] {
+            let mut vertices = vec![
+                vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+                vertex([1.0, 1.0, 1.0], [0.5, 0.5]),
+                vertex([2.0, 2.0, 2.0], [1.0, 1.0]),
+            ];
+            let mut indices = vec![0, 1, 2];
+            generate_normals(&mut vertices, &mut indices, mode);
+            for vertex in &vertices {
+                assert_normal(vertex, Vector3::unit_y());
+            }
+        }
+    }
+
+    #[test]
+    fn obj_meshes_are_checked() {
+        let triangle = |indices: Vec<u32>, normals: Vec<f32>| {
+            let mesh = tobj::Mesh {
+                positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                normals,
+                indices,
+                ..Default::default()
+            };
+            tobj::Model::new(mesh, "triangle".to_string())
+        };
+        assert!(check_obj_mesh(&triangle(vec![0, 1, 2], Vec::new()), "a.obj", 0).is_ok());
+
+        let error = check_obj_mesh(&triangle(vec![0, 1, 3], Vec::new()), "a.obj", 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Object \"triangle\" in \"a.obj\" references vertex 3 but only has 3"
+        );
+        let error = check_obj_mesh(&triangle(vec![0, 1, 2], vec![0.0; 6]), "a.obj", 0).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Object \"triangle\" in \"a.obj\" has 2 normals for 3 vertices"
+        );
+    }
 }
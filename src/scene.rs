@@ -0,0 +1,194 @@
+use cgmath::{EuclideanSpace, Matrix4, One, Quaternion, SquareMatrix, Vector3};
+
+use crate::light::{Light, LightUniform};
+use crate::sdf::{SdfObject, SdfObjectUniform};
+
+/// Position, rotation and scale of a node relative to its parent
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+pub enum NodeKind {
+    /// Only groups its children
+    Group,
+    /// An instance of the model at this index in `State`'s model list
+    Model(usize),
+    Sdf(SdfObject),
+    Light(Light),
+    /// A viewpoint. Cameras look down their local +X axis, matching a `Camera` with zero yaw.
+    Camera,
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    pub kind: NodeKind,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world_transform: Matrix4<f32>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// As of the last call to `Scene::update_world_transforms`
+    pub fn world_transform(&self) -> Matrix4<f32> {
+        self.world_transform
+    }
+}
+
+/// Hierarchy of everything in the world. Local transforms are edited freely and propagated down
+/// to world transforms once per frame by `update_world_transforms`.
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<S: Into<String>>(
+        &mut self,
+        parent: Option<NodeId>,
+        name: S,
+        transform: Transform,
+        kind: NodeKind,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.into(),
+            transform,
+            kind,
+            parent,
+            children: Vec::new(),
+            world_transform: Matrix4::identity(),
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    /// Moves `id` under `parent`, keeping its local transform. Fails if that would make a cycle.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> anyhow::Result<()> {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            anyhow::ensure!(
+                current != id,
+                "Can't parent {:?} to its own descendant",
+                self.nodes[id.0].name
+            );
+            ancestor = self.nodes[current.0].parent;
+        }
+
+        if let Some(old_parent) = self.nodes[id.0].parent {
+            self.nodes[old_parent.0]
+                .children
+                .retain(|&child| child != id);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        self.nodes[id.0].parent = parent;
+        Ok(())
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    pub fn update_world_transforms(&mut self) {
+        let mut stack = self
+            .nodes()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| (id, Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_transform)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            node.world_transform = parent_transform * node.transform.matrix();
+            let world_transform = node.world_transform;
+            stack.extend(node.children.iter().map(|&child| (child, world_transform)));
+        }
+    }
+
+    /// World transforms of every instance of the model at `model`
+    pub fn model_instances(&self, model: usize) -> impl Iterator<Item = Matrix4<f32>> + '_ {
+        self.nodes
+            .iter()
+            .filter(move |node| matches!(node.kind, NodeKind::Model(m) if m == model))
+            .map(|node| node.world_transform)
+    }
+
+    pub fn light_uniforms(&self) -> Vec<LightUniform> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match &node.kind {
+                NodeKind::Light(light) => {
+                    let mut uniform = LightUniform::new();
+                    let position = node.world_transform.w.truncate();
+                    uniform.update_values(light, cgmath::Point3::from_vec(position));
+                    Some(uniform)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// SDF objects are combined in the order they were added to the scene
+    pub fn sdf_uniforms(&self) -> Vec<SdfObjectUniform> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match &node.kind {
+                NodeKind::Sdf(object) => {
+                    let mut uniform = SdfObjectUniform::new();
+                    uniform.update_values(object, node.world_transform);
+                    Some(uniform)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,431 @@
+//! Scene description files, written in [RON](https://github.com/ron-rs/ron).
+//!
+//! A scene file lists the environment, the models it uses and a tree of nodes:
+//!
+//! ```ron
+//! (
+//!     environment: (
+//!         // Equirectangular panorama in res/. Without one the background is a flat colour.
+//!         skybox: Some("lago_disola_4k.exr"),
+//!         background: (0.1, 0.2, 0.3),
+//!     ),
+//!     models: [
+//!         (
+//!             name: "cube",
+//!             // OBJ, glTF or GLB file in res/
+//!             file: "cube.obj",
+//!             // Only used for OBJ meshes without normals. Smooth or Faceted.
+//!             normals: Smooth,
+//!             // Replaces the properties of the named materials in the model's own files
+//!             materials: {
+//!                 "Material.001": (diffuse: (1.0, 0.5, 0.5), shininess: 32.0),
+//!             },
+//!         ),
+//!     ],
+//!     nodes: [
+//!         (
+//!             name: "camera",
+//!             transform: (position: (0.0, 1.0, 5.0), rotation: (0.0, 0.7071068, 0.0, 0.7071068)),
+//!             kind: Camera,
+//!         ),
+//!         (
+//!             name: "light",
+//!             transform: (position: (5.0, 5.0, 5.0)),
+//!             kind: Light((colour: (1.0, 0.8, 0.8), strength: 1.0, radius: 10.0)),
+//!         ),
+//!         (
+//!             name: "shapes",
+//!             transform: (scale: (2.0, 2.0, 2.0)),
+//!             children: [
+//!                 (name: "box", kind: Sdf((shape: Box(half_extents: (1.0, 0.8, 0.7)), operation: Union))),
+//!                 (name: "ball", kind: Sdf((shape: Sphere(radius: 0.9), operation: Intersect))),
+//!             ],
+//!         ),
+//!         (name: "cube", kind: Model("cube")),
+//!     ],
+//! )
+//! ```
+//!
+//! A scene can also have `camera_tracks`, keyframed camera paths that are described in
+//! `camera_path`, and a `projection`, `lens` and `stereo` view:
+//!
+//! ```ron
+//! // Perspective, Orthographic, Fisheye or Equirectangular. Angles are in degrees.
+//! projection: (mode: Fisheye, fov: 45.0, ortho_height: 10.0, fisheye_fov: 180.0),
+//! // Depth of field. Without a focus_distance the lens focuses on the centre of the screen, and
+//! // without blades the aperture is round.
+//! lens: (aperture: 0.2, focus_distance: Some(5.0), blades: 6),
+//! // Off, SideBySide or Anaglyph (red/cyan). Eyes are interocular apart, and objects at the
+//! // convergence distance look to be at the depth of the screen.
+//! stereo: (mode: Anaglyph, interocular: 0.065, convergence: 5.0),
+//! ```
+//!
+//! Every node has a `name`, and optionally a `transform` relative to its parent, a `kind` (`Group`
+//! by default) and `children`. Transforms have a `position`, a `rotation` quaternion written as
+//! `(x, y, z, w)` and a `scale`, any of which can be left out. Cameras look down their local +X
+//! axis, and the first camera in the file is the one the view starts from. SDF objects are
+//! combined in the order they appear, each with its operation applied to everything before it.
+//!
+//! Saving writes fields that are left at their defaults out, so a file written by `save` reads
+//! back and saves again unchanged.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use cgmath::{Quaternion, Vector3};
+
+use crate::camera::{Lens, Projection, ProjectionMode, Stereo};
+use crate::camera_path::CameraTrack;
+use crate::light::Light;
+use crate::model::MaterialProperties;
+use crate::resources::NormalGeneration;
+use crate::scene::{NodeId, NodeKind, Scene, Transform};
+use crate::sdf::SdfObject;
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneDescription {
+    pub environment: EnvironmentDescription,
+    pub models: Vec<ModelDescription>,
+    pub nodes: Vec<NodeDescription>,
+    /// Keyframed camera animation, described in `camera_path`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub camera_tracks: Vec<CameraTrack>,
+    #[serde(skip_serializing_if = "is_default")]
+    pub projection: ProjectionDescription,
+    #[serde(skip_serializing_if = "is_default")]
+    pub lens: Lens,
+    #[serde(skip_serializing_if = "is_default")]
+    pub stereo: Stereo,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProjectionDescription {
+    pub mode: ProjectionMode,
+    /// Vertical field of view for perspective
+    pub fov: f32,
+    /// Height of the view in world units for orthographic
+    pub ortho_height: f32,
+    /// Angle across the image circle for fisheye
+    pub fisheye_fov: f32,
+}
+
+impl ProjectionDescription {
+    pub fn apply(&self, projection: &mut Projection) {
+        projection.mode = self.mode;
+        projection.set_fovy(cgmath::Deg(self.fov));
+        projection.ortho_height = self.ortho_height;
+        projection.fisheye_fov = cgmath::Deg(self.fisheye_fov).into();
+    }
+}
+
+impl Default for ProjectionDescription {
+    fn default() -> Self {
+        Self {
+            mode: ProjectionMode::Perspective,
+            fov: 45.0,
+            ortho_height: 10.0,
+            fisheye_fov: 180.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct EnvironmentDescription {
+    pub skybox: Option<String>,
+    /// sRGB colour used in place of the skybox when there isn't one
+    pub background: [f32; 3],
+}
+
+impl EnvironmentDescription {
+    pub fn background_bytes(&self) -> [u8; 4] {
+        let [r, g, b] = self
+            .background
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        [r, g, b, 255]
+    }
+}
+
+impl Default for EnvironmentDescription {
+    fn default() -> Self {
+        Self {
+            skybox: None,
+            background: [0.1, 0.2, 0.3],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModelDescription {
+    pub name: String,
+    pub file: String,
+    #[serde(
+        default = "default_normals",
+        skip_serializing_if = "is_default_normals"
+    )]
+    pub normals: NormalGeneration,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, MaterialProperties>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NodeDescription {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub transform: TransformDescription,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub kind: NodeKindDescription,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum NodeKindDescription {
+    #[default]
+    Group,
+    /// Refers to a model by its name in the `models` list
+    Model(String),
+    Sdf(SdfObject),
+    Light(Light),
+    Camera,
+}
+
+/// `Transform` with plain arrays, so the file doesn't depend on cgmath's serialised layout
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TransformDescription {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub position: [f32; 3],
+    #[serde(skip_serializing_if = "is_identity")]
+    pub rotation: [f32; 4],
+    #[serde(skip_serializing_if = "is_one")]
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        Transform::default().into()
+    }
+}
+
+impl From<Transform> for TransformDescription {
+    fn from(transform: Transform) -> Self {
+        let Quaternion { v, s } = transform.rotation;
+        Self {
+            position: transform.position.into(),
+            rotation: [v.x, v.y, v.z, s],
+            scale: transform.scale.into(),
+        }
+    }
+}
+
+impl From<TransformDescription> for Transform {
+    fn from(description: TransformDescription) -> Self {
+        let [x, y, z, w] = description.rotation;
+        Self {
+            position: description.position.into(),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: Vector3::from(description.scale),
+        }
+    }
+}
+
+fn default_normals() -> NormalGeneration {
+    NormalGeneration::Smooth
+}
+
+fn is_default_normals(normals: &NormalGeneration) -> bool {
+    *normals == default_normals()
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn is_zero(value: &[f32; 3]) -> bool {
+    *value == [0.0; 3]
+}
+
+fn is_one(value: &[f32; 3]) -> bool {
+    *value == [1.0; 3]
+}
+
+fn is_identity(value: &[f32; 4]) -> bool {
+    *value == [0.0, 0.0, 0.0, 1.0]
+}
+
+impl SceneDescription {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let description: Self = ron::from_str(text)?;
+        for track in &description.camera_tracks {
+            let keyframes = &track.keyframes;
+            anyhow::ensure!(
+                !keyframes.is_empty(),
+                "Camera track {:?} has no keyframes",
+                track.name
+            );
+            anyhow::ensure!(
+                keyframes.windows(2).all(|w| w[0].time <= w[1].time),
+                "Camera track {:?} has keyframes out of time order",
+                track.name
+            );
+        }
+        if let Some(distance) = description.lens.focus_distance {
+            anyhow::ensure!(
+                distance > 0.0,
+                "The lens's focus_distance must be positive, found {}",
+                distance
+            );
+        }
+        anyhow::ensure!(
+            description.stereo.convergence > 0.0,
+            "The stereo convergence must be positive, found {}",
+            description.stereo.convergence
+        );
+        Ok(description)
+    }
+
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let config = ron::ser::PrettyConfig::new().indentor("    ".to_string());
+        let mut text = ron::ser::to_string_pretty(self, config)?;
+        text.push('\n');
+        Ok(text)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read scene file {:?}", path))?;
+        Self::parse(&text).with_context(|| format!("Couldn't parse scene file {:?}", path))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_ron()?)
+            .with_context(|| format!("Couldn't write scene file {:?}", path))
+    }
+
+    /// Builds the node tree. `Model` nodes refer to models by their index in `models`.
+    pub fn build_scene(&self) -> anyhow::Result<Scene> {
+        let mut scene = Scene::new();
+        for node in &self.nodes {
+            self.add_node(&mut scene, None, node)?;
+        }
+        Ok(scene)
+    }
+
+    fn add_node(
+        &self,
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+        node: &NodeDescription,
+    ) -> anyhow::Result<()> {
+        let kind = match &node.kind {
+            NodeKindDescription::Group => NodeKind::Group,
+            NodeKindDescription::Model(name) => NodeKind::Model(
+                self.models
+                    .iter()
+                    .position(|model| &model.name == name)
+                    .with_context(|| {
+                        format!("Node {:?} refers to unknown model {:?}", node.name, name)
+                    })?,
+            ),
+            NodeKindDescription::Sdf(object) => NodeKind::Sdf(*object),
+            NodeKindDescription::Light(light) => NodeKind::Light(*light),
+            NodeKindDescription::Camera => NodeKind::Camera,
+        };
+        let id = scene.add(parent, node.name.clone(), node.transform.into(), kind);
+        for child in &node.children {
+            self.add_node(scene, Some(id), child)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the node tree with the current contents of `scene`
+    pub fn update_nodes(&mut self, scene: &Scene) {
+        self.nodes = scene
+            .nodes()
+            .filter(|(_, node)| node.parent().is_none())
+            .map(|(id, _)| self.describe_node(scene, id))
+            .collect();
+    }
+
+    fn describe_node(&self, scene: &Scene, id: NodeId) -> NodeDescription {
+        let node = scene.node(id);
+        NodeDescription {
+            name: node.name.clone(),
+            transform: node.transform.into(),
+            kind: match &node.kind {
+                NodeKind::Group => NodeKindDescription::Group,
+                NodeKind::Model(model) => {
+                    NodeKindDescription::Model(self.models[*model].name.clone())
+                }
+                NodeKind::Sdf(object) => NodeKindDescription::Sdf(*object),
+                NodeKind::Light(light) => NodeKindDescription::Light(*light),
+                NodeKind::Camera => NodeKindDescription::Camera,
+            },
+            children: node
+                .children()
+                .iter()
+                .map(|&child| self.describe_node(scene, child))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scene_round_trips() {
+        let text =
+            std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/res/scene.ron")).unwrap();
+        let mut description = SceneDescription::parse(&text).unwrap();
+        let scene = description.build_scene().unwrap();
+        description.update_nodes(&scene);
+        assert_eq!(description.to_ron().unwrap(), text);
+    }
+
+    #[test]
+    fn camera_tracks_need_keyframes_in_order() {
+        let keyframe = |time| {
+            format!(
+                "(time: {}, position: (0, 0, 0), yaw: 0, pitch: 0, fov: 45)",
+                time
+            )
+        };
+        let scene = |keyframes: &[String]| {
+            format!(
+                "(nodes: [], camera_tracks: [(name: \"track\", keyframes: [{}])])",
+                keyframes.join(", ")
+            )
+        };
+        assert!(SceneDescription::parse(&scene(&[keyframe(0.0), keyframe(1.0)])).is_ok());
+        let error = SceneDescription::parse(&scene(&[])).unwrap_err();
+        assert_eq!(error.to_string(), "Camera track \"track\" has no keyframes");
+        let error = SceneDescription::parse(&scene(&[keyframe(1.0), keyframe(0.0)])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Camera track \"track\" has keyframes out of time order"
+        );
+    }
+
+    #[test]
+    fn view_distances_must_be_positive() {
+        assert!(SceneDescription::parse("(nodes: [], lens: (focus_distance: Some(2.0)))").is_ok());
+        let error = SceneDescription::parse("(nodes: [], lens: (focus_distance: Some(0.0)))");
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "The lens's focus_distance must be positive, found 0"
+        );
+        let error = SceneDescription::parse("(nodes: [], stereo: (convergence: -1.0))");
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "The stereo convergence must be positive, found -1"
+        );
+    }
+}
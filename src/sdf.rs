@@ -0,0 +1,64 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SdfShape {
+    Sphere { radius: f32 },
+    Box { half_extents: [f32; 3] },
+}
+
+/// How an object's distance is combined with the objects before it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SdfOperation {
+    Union,
+    Difference,
+    Intersect,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SdfObject {
+    pub shape: SdfShape,
+    pub operation: SdfOperation,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SdfObjectUniform {
+    pub inverse_transform: [[f32; 4]; 4],
+    pub params: [f32; 4],
+    /// 1 for a sphere and 2 for a box. 0 is reserved for "no shape", so a zeroed uniform is
+    /// ignored by the shader.
+    pub shape: u32,
+    /// 0 for union, 1 for difference and 2 for intersect
+    pub operation: u32,
+    /// Converts local distances back to world space
+    pub scale: f32,
+    _padding: u32,
+}
+
+impl SdfObjectUniform {
+    pub fn new() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+
+    pub fn update_values(&mut self, object: &SdfObject, transform: Matrix4<f32>) {
+        self.inverse_transform = transform.invert().unwrap_or_else(Matrix4::identity).into();
+        // Distances are only preserved by uniform scale, so the smallest axis keeps the bound safe
+        self.scale = transform
+            .x
+            .truncate()
+            .magnitude()
+            .min(transform.y.truncate().magnitude())
+            .min(transform.z.truncate().magnitude());
+        (self.shape, self.params) = match object.shape {
+            SdfShape::Sphere { radius } => (1, [radius, 0.0, 0.0, 0.0]),
+            SdfShape::Box {
+                half_extents: [x, y, z],
+            } => (2, [x, y, z, 0.0]),
+        };
+        self.operation = match object.operation {
+            SdfOperation::Union => 0,
+            SdfOperation::Difference => 1,
+            SdfOperation::Intersect => 2,
+        };
+    }
+}
@@ -0,0 +1,22 @@
+// Signed distance functions and the operations combining them
+
+fn sd_sphere(p: vec3<f32>, r: f32) -> f32 {
+    return length(p) - r;
+}
+
+fn sd_box(p: vec3<f32>, b: vec3<f32>) -> f32 {
+    let q = abs(p) - b;
+    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+fn op_union(d1: f32, d2: f32) -> f32 {
+    return min(d1, d2);
+}
+
+fn op_difference(d1: f32, d2: f32) -> f32 {
+    return max(d1, -d2);
+}
+
+fn op_intersect(d1: f32, d2: f32) -> f32 {
+    return max(d1, d2);
+}
@@ -0,0 +1,156 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::{preprocessor, uniforms};
+
+/// A WGSL file in `src/`. Shaders are normally compiled into the binary, but in development mode
+/// they're read from the source tree instead so they can be edited while the app is running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShaderFile {
+    pub name: &'static str,
+    embedded: &'static str,
+}
+
+pub const FULLSCREEN: ShaderFile = ShaderFile {
+    name: "fullscreen.wgsl",
+    embedded: include_str!("fullscreen.wgsl"),
+};
+
+pub const GBUFFER: ShaderFile = ShaderFile {
+    name: "gbuffer.wgsl",
+    embedded: include_str!("gbuffer.wgsl"),
+};
+
+pub const SKYBOX: ShaderFile = ShaderFile {
+    name: "skybox.wgsl",
+    embedded: include_str!("skybox.wgsl"),
+};
+
+/// Drawn over the frame while a reload is failing. It's always the compiled in copy, so it isn't
+/// in `SHADERS` with the ones that are reloaded.
+pub const ERROR_OVERLAY: ShaderFile = ShaderFile {
+    name: "error_overlay.wgsl",
+    embedded: include_str!("error_overlay.wgsl"),
+};
+
+pub const SHADERS: [ShaderFile; 3] = [FULLSCREEN, GBUFFER, SKYBOX];
+
+/// Files shaders can `#include`, besides the generated `uniforms.wgsl`
+pub const INCLUDES: [ShaderFile; 1] = [ShaderFile {
+    name: "sdf.wgsl",
+    embedded: include_str!("sdf.wgsl"),
+}];
+
+impl ShaderFile {
+    pub fn path(&self) -> PathBuf {
+        [env!("CARGO_MANIFEST_DIR"), "src", self.name]
+            .iter()
+            .collect()
+    }
+
+    /// The shader with its `#include`s and other directives expanded
+    pub fn source(&self, from_disk: bool) -> anyhow::Result<String> {
+        Ok(self.preprocess(from_disk)?.source)
+    }
+
+    fn preprocess(&self, from_disk: bool) -> anyhow::Result<preprocessor::Preprocessed> {
+        let source = self.raw_source(from_disk)?;
+        preprocessor::preprocess(self.name, &source, |name| {
+            if name == uniforms::INCLUDE_NAME {
+                return Ok(Cow::Owned(uniforms::wgsl()));
+            }
+            INCLUDES
+                .iter()
+                .find(|include| include.name == name)
+                .with_context(|| format!("No include file called {:?}", name))?
+                .raw_source(from_disk)
+        })
+    }
+
+    fn raw_source(&self, from_disk: bool) -> anyhow::Result<Cow<'static, str>> {
+        if from_disk {
+            let path = self.path();
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Couldn't read shader {:?}", path))?;
+            Ok(Cow::Owned(source))
+        } else {
+            Ok(Cow::Borrowed(self.embedded))
+        }
+    }
+
+    /// Runs `build` on this shader's source inside a validation error scope. Any shader or
+    /// pipeline errors it causes, which wgpu would otherwise panic on, are returned instead, with
+    /// their locations pointing at the file and line the code came from.
+    pub fn build<T>(
+        &self,
+        device: &wgpu::Device,
+        from_disk: bool,
+        build: impl FnOnce(&str) -> T,
+    ) -> anyhow::Result<T> {
+        let preprocessed = self.preprocess(from_disk)?;
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let built = build(&preprocessed.source);
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => {
+                let error = preprocessed.locate_errors(&error.to_string());
+                anyhow::bail!("Couldn't build {:?}: {}", self.path(), error)
+            }
+            None => Ok(built),
+        }
+    }
+}
+
+/// Parses and validates the compiled in copy of `shader` with naga, so shaders can be checked
+/// without a GPU
+#[cfg(test)]
+pub fn validate(shader: ShaderFile) -> (naga::Module, naga::valid::ModuleInfo) {
+    let source = shader.source(false).unwrap();
+    let module = naga::front::wgsl::parse_str(&source)
+        .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&source)));
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .unwrap_or_else(|e| panic!("{} doesn't validate: {:?}", shader.name, e));
+    (module, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_shader_validates() {
+        for shader in SHADERS.iter().chain([&ERROR_OVERLAY]) {
+            validate(*shader);
+        }
+    }
+
+    #[test]
+    fn every_wgsl_file_is_known() {
+        let known: Vec<_> = SHADERS
+            .iter()
+            .chain(&INCLUDES)
+            .chain([&ERROR_OVERLAY])
+            .map(|shader| shader.name)
+            .collect();
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "wgsl")
+            {
+                let name = path.file_name().unwrap().to_str().unwrap();
+                assert!(
+                    known.contains(&name),
+                    "{} isn't listed in shaders.rs, so it isn't tested",
+                    name
+                );
+            }
+        }
+    }
+}
@@ -1,20 +1,91 @@
-use crate::camera::{Camera, CameraController, CameraUniform, Projection};
-use crate::instance::Instance;
-use crate::light::{Light, LightUniform};
+use crate::autofocus::FocusProbe;
+use crate::bindings::{self, Group};
+use crate::bookmarks::{Bookmark, Bookmarks};
+use crate::camera::{
+    Camera, CameraUniform, Lens, Projection, ProjectionMode, Stereo, StereoMode, ViewOffset,
+};
+use crate::camera_controller::{
+    CameraController, FlightController, FlyController, OrbitController,
+};
+use crate::camera_path::{CameraPose, CameraTrack, TrackRecorder};
+use crate::capture;
+use crate::context::{self, RenderContext, RenderTarget};
+use crate::input::{self, ActionEvent, Binding, InputMap};
+use crate::instance::InstanceRaw;
+use crate::light::LightUniform;
 use crate::model::{self, DrawModel, Vertex};
 use crate::resources;
+use crate::scene::{NodeId, NodeKind, Scene, Transform};
+use crate::scene_file::{EnvironmentDescription, ModelDescription, SceneDescription};
+use crate::sdf::SdfObjectUniform;
+use crate::shaders;
 use crate::texture::Texture;
-use cgmath::{InnerSpace, Rotation3};
+use crate::watcher::FileWatcher;
+use anyhow::Context;
+use cgmath::{EuclideanSpace, InnerSpace};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
 use wgpu::util::DeviceExt;
-use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, WindowEvent};
-use winit::window::Window;
-
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    0.0,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-);
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+/// Loaded from `res/` when no scene file is given on the command line
+const DEFAULT_SCENE: &str = "scene.ron";
+/// Actions the app handles itself rather than passing on to the camera controller, with their
+/// default bindings. Each happens once when it's pressed.
+const APP_ACTIONS: &[(&str, &[Binding])] = &[
+    (input::QUIT, &[Binding::Key(VirtualKeyCode::Escape)]),
+    (input::SAVE_SCENE, &[Binding::Key(VirtualKeyCode::F5)]),
+    (
+        input::RECORD_CAMERA_TRACK,
+        &[Binding::Key(VirtualKeyCode::F6)],
+    ),
+    (
+        input::PLAY_CAMERA_TRACK,
+        &[Binding::Key(VirtualKeyCode::F7)],
+    ),
+    (
+        input::NEXT_CAMERA_TRACK,
+        &[Binding::Key(VirtualKeyCode::F8)],
+    ),
+    (input::SCREENSHOT, &[Binding::Key(VirtualKeyCode::F12)]),
+    (
+        input::NEXT_CAMERA_CONTROLLER,
+        &[Binding::Key(VirtualKeyCode::C)],
+    ),
+    (input::NEXT_PROJECTION, &[Binding::Key(VirtualKeyCode::P)]),
+    (input::NEXT_STEREO_MODE, &[Binding::Key(VirtualKeyCode::V)]),
+];
+/// Default keys for `input::BOOKMARKS`
+const BOOKMARK_KEYS: [VirtualKeyCode; 10] = [
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+];
+/// Holds the average of the frames rendered since the view last changed, in full precision so
+/// that hundreds of frames can add up
+const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+/// Focus distance for lenses that focus themselves, until they've measured one
+const DEFAULT_FOCUS_DISTANCE: f32 = 5.0;
+/// Fraction the distance under the centre of the screen has to change by before autofocus
+/// refocuses, starting the blur over
+const REFOCUS_THRESHOLD: f32 = 0.01;
+/// `MAXIMUM_TRACE_DISTANCE` in `fullscreen.wgsl`, the distance given for rays that hit nothing
+const MAXIMUM_TRACE_DISTANCE: f32 = 1000.0;
+/// Channels the lighting pass writes, with a pipeline for each: everything for a single view, or
+/// one eye's share of a red/cyan anaglyph. The left eye writes the alpha autofocus measures.
+const FULLSCREEN_CHANNELS: [wgpu::ColorWrites; 3] = [
+    wgpu::ColorWrites::ALL,
+    wgpu::ColorWrites::RED.union(wgpu::ColorWrites::ALPHA),
+    wgpu::ColorWrites::GREEN.union(wgpu::ColorWrites::BLUE),
+];
 const FULLSCREEN_VERTICES: &[[f32; 3]] = &[
     [-1.0, 1.0, 0.0],
     [1.0, 1.0, 0.0],
@@ -26,22 +97,63 @@ const FULLSCREEN_INDICES: &[u16] = &[0, 2, 1, 1, 2, 3];
 pub struct State {
     instance: wgpu::Instance,
     adapter: wgpu::Adapter,
-    surface: wgpu::Surface,
+    target: RenderTarget,
     pub device: wgpu::Device,
     queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
     camera: Camera,
+    /// The view from one eye, which is the whole frame unless stereo is side by side
     camera_projection: Projection,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    camera_controller: CameraController,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
+    /// One for each eye, of which only the first is used while stereo is off
+    eye_cameras: [EyeCamera; 2],
+    stereo: Stereo,
+    lens: Lens,
+    /// The lens's focus distance, or the last one autofocus measured
+    focus_distance: f32,
+    focus_probe: FocusProbe,
+    /// Every way of moving the camera, switched between with `next_camera_controller`
+    camera_controllers: Vec<Box<dyn CameraController>>,
+    active_controller: usize,
+    input_map: InputMap,
+    /// Keys and buttons only count while the window has focus
+    focused: bool,
+    quit_requested: bool,
+    /// Track driving the camera in place of the fly controls, while it's playing
+    camera_playback: Option<CameraPlayback>,
+    /// Track being recorded from the fly camera
+    track_recorder: Option<TrackRecorder>,
+    /// Viewpoints stored under the number keys, saved beside the scene file
+    bookmarks: Bookmarks,
+    /// Seconds the camera takes to move to a recalled bookmark
+    bookmark_transition: f32,
+    /// Path to the bookmark being recalled, with how far along it the camera is
+    bookmark_flight: Option<(CameraTrack, f32)>,
+    scene: Scene,
+    scene_description: SceneDescription,
+    scene_path: PathBuf,
+    /// Set in development mode, where shaders and the scene file are reloaded when they change
+    watcher: Option<FileWatcher>,
+    /// Files whose latest reload failed. The last working version of each stays in use, and a
+    /// tint is drawn over the frame until they're fixed.
+    failed_reloads: Vec<PathBuf>,
+    error_overlay_pipeline: wgpu::RenderPipeline,
+    /// Where to save the next frame rendered
+    screenshot_requests: Vec<PathBuf>,
+    /// Frames being copied back from the GPU, saved once they arrive
+    pending_screenshots: Vec<(capture::Readback, PathBuf)>,
+    camera_node: NodeId,
+    models: Vec<model::Model>,
+    instance_buffer: DynamicBuffer,
+    mesh_draws: Vec<MeshDraw>,
     depth_texture: Texture,
-    mouse_pressed: bool,
-    fullscreen_pipeline: wgpu::RenderPipeline,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    gbuffer_pipeline_layout: wgpu::PipelineLayout,
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    fullscreen_pipeline_layout: wgpu::PipelineLayout,
+    /// One for each of `FULLSCREEN_CHANNELS`
+    fullscreen_pipelines: Vec<wgpu::RenderPipeline>,
+    fullscreen_bind_group_layout: wgpu::BindGroupLayout,
     fullscreen_bind_group: wgpu::BindGroup,
     peel_depth_texture: Texture,
     fullscreen_vertex_buffer: wgpu::Buffer,
@@ -50,51 +162,157 @@ pub struct State {
     first_depth_texture: Texture,
     position_texture: Texture,
     normal_texture: Texture,
+    specular_texture: Texture,
+    emissive_texture: Texture,
+    /// Copy of the accumulation texture from the frame before, which the lighting pass averages
+    /// the new frame into
     last_frame_texture: Texture,
+    accumulation_texture: Texture,
+    /// Everything that went into the frames averaged so far, to tell when they have to start over
+    accumulated_inputs: Vec<u8>,
+    skybox_texture: Texture,
+    panorama_texture: Texture,
+    skybox_bind_group_layout: wgpu::BindGroupLayout,
+    skybox_pipeline_layout: wgpu::PipelineLayout,
+    /// Frames averaged into `last_frame_texture`
     frame_count: f32,
     frame_count_buffer: wgpu::Buffer,
     utils_bind_group: wgpu::BindGroup,
-    lights_bind_group: wgpu::BindGroup,
-    lights: Vec<Light>,
-    lights_buffer: wgpu::Buffer,
+    scene_bind_group_layout: wgpu::BindGroupLayout,
+    scene_bind_group: wgpu::BindGroup,
+    lights_buffer: DynamicBuffer,
+    sdf_buffer: DynamicBuffer,
+}
+
+/// Position in one of the scene description's camera tracks
+struct CameraPlayback {
+    track: usize,
+    time: f32,
+    playing: bool,
+}
+
+/// One instanced draw of a mesh, with its instances' range in the instance buffer
+struct MeshDraw {
+    model: usize,
+    mesh: usize,
+    instances: Range<u32>,
+}
+
+/// A buffer rewritten every frame whose contents may change length
+struct DynamicBuffer {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+}
+
+impl DynamicBuffer {
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        contents: &[u8],
+        usage: wgpu::BufferUsages,
+    ) -> Self {
+        let usage = usage | wgpu::BufferUsages::COPY_DST;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        });
+        Self {
+            buffer,
+            size: contents.len() as wgpu::BufferAddress,
+            label,
+            usage,
+        }
+    }
+
+    /// Returns true if the buffer had to be recreated, so any bind groups using it are stale
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, contents: &[u8]) -> bool {
+        if contents.len() as wgpu::BufferAddress == self.size {
+            queue.write_buffer(&self.buffer, 0, contents);
+            false
+        } else {
+            *self = Self::new(device, self.label, contents, self.usage);
+            true
+        }
+    }
+}
+
+/// The camera uniform for one eye, and the bind group it's drawn through
+struct EyeCamera {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl EyeCamera {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("camera_buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+        Self { buffer, bind_group }
+    }
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_LINE,
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
-            format: surface.get_supported_formats(&adapter)[0],
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+    pub async fn new(
+        context: RenderContext,
+        scene_path: Option<PathBuf>,
+        dev_mode: bool,
+    ) -> anyhow::Result<Self> {
+        let scene_path = match scene_path {
+            Some(path) => Some(path),
+            // Development mode edits the default scene in the source tree rather than the copy
+            None if dev_mode => Some(
+                [env!("CARGO_MANIFEST_DIR"), "res", DEFAULT_SCENE]
+                    .iter()
+                    .collect(),
+            ),
+            None => None,
+        };
+        let scene_description = match &scene_path {
+            Some(path) => SceneDescription::load(path)?,
+            None => SceneDescription::parse(&resources::load_string(DEFAULT_SCENE).await?)
+                .context("Couldn't parse the default scene")?,
         };
-        surface.configure(&device, &config);
+        // Saving the default scene writes a copy to the working directory rather than into res/
+        let scene_path = scene_path.unwrap_or_else(|| PathBuf::from(DEFAULT_SCENE));
+        let bookmarks = Bookmarks::load_or_empty(&scene_path);
+        let mut scene = scene_description.build_scene()?;
+        scene.update_world_transforms();
+
+        let watcher = if dev_mode {
+            let mut files: Vec<PathBuf> = shaders::SHADERS
+                .iter()
+                .chain(&shaders::INCLUDES)
+                .map(|shader| shader.path())
+                .collect();
+            files.push(scene_path.clone());
+            Some(FileWatcher::new(&files)?)
+        } else {
+            None
+        };
+
+        let RenderContext {
+            instance,
+            adapter,
+            device,
+            queue,
+            target,
+            config,
+        } = context;
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
 
         println!("Output config: {:#?}", config);
 
@@ -158,6 +376,30 @@ impl State {
             wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
         );
 
+        let specular_texture = Texture::create_color_texture(
+            &device,
+            wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            "specular_texture",
+            wgpu::TextureFormat::Rgba16Float,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+
+        let emissive_texture = Texture::create_color_texture(
+            &device,
+            wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            "emissive_texture",
+            wgpu::TextureFormat::Rgba16Float,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+
         let last_frame_texture = Texture::create_color_texture(
             &device,
             wgpu::Extent3d {
@@ -166,13 +408,23 @@ impl State {
                 depth_or_array_layers: 1,
             },
             "last_frame_texture",
-            config.format,
+            ACCUMULATION_FORMAT,
             wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         );
+        let accumulation_texture = Texture::create_color_texture(
+            &device,
+            wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            "accumulation_texture",
+            ACCUMULATION_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
 
-        let panorama_texture = resources::load_texture("lago_disola_4k.exr", &device, &queue)
-            .await
-            .unwrap();
+        let panorama_texture =
+            load_panorama(&device, &queue, &scene_description.environment).await?;
 
         let skybox_texture = Texture::create_cubemap_texture(
             &device,
@@ -188,216 +440,55 @@ impl State {
 
         let diffuse_bytes = include_bytes!("happy-tree.png");
         let diffuse_texture =
-            Texture::from_bytes(&device, &queue, diffuse_bytes, "diffuse_texture").unwrap();
+            Texture::from_bytes(&device, &queue, diffuse_bytes, "diffuse_texture", false).unwrap();
 
         let fullscreen_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        // Albedo texture
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Albedo sampler
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Depth texture
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Depth sampler
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Position texture
-                        binding: 4,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Position sampler
-                        binding: 5,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Normal texture
-                        binding: 6,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Normal sampler
-                        binding: 7,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Last frame texture
-                        binding: 8,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Last frame sampler
-                        binding: 9,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Skybox texture
-                        binding: 10,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::Cube,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        // Skybox sampler
-                        binding: 11,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+                entries: Group::Fullscreen.entries(),
                 label: Some("fullscreen_bind_group_layout"),
             });
 
-        let fullscreen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &fullscreen_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&albedo_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&albedo_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&peel_depth_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&peel_depth_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(&position_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::Sampler(&position_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::TextureView(&last_frame_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 9,
-                    resource: wgpu::BindingResource::Sampler(&last_frame_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 10,
-                    resource: wgpu::BindingResource::TextureView(&skybox_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 11,
-                    resource: wgpu::BindingResource::Sampler(&skybox_texture.sampler),
-                },
+        let fullscreen_bind_group = create_fullscreen_bind_group(
+            &device,
+            &fullscreen_bind_group_layout,
+            &[
+                &albedo_texture,
+                &peel_depth_texture,
+                &position_texture,
+                &normal_texture,
+                &last_frame_texture,
+                &skybox_texture,
+                &specular_texture,
+                &emissive_texture,
             ],
-            label: Some("fullscreen_bind_group"),
-        });
-
-        let camera = Camera::new((0.0, 0.0, 0.0), cgmath::Deg(0.0), cgmath::Deg(0.0));
-        let camera_projection =
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
+        );
 
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, &camera_projection);
+        let camera_node = first_camera(&mut scene);
+        let camera = camera_from_node(&scene, camera_node);
+        let stereo = scene_description.stereo.clone();
+        let mut camera_projection = Projection::new(
+            stereo.eye_width(config.width),
+            config.height,
+            cgmath::Deg(45.0),
+            0.1,
+            100.0,
+        );
+        scene_description.projection.apply(&mut camera_projection);
 
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("camera_buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let lens = scene_description.lens.clone();
+        let focus_distance = lens.focus_distance.unwrap_or(DEFAULT_FOCUS_DISTANCE);
+        let focus_probe = FocusProbe::new(&device);
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                entries: Group::Camera.entries(),
                 label: Some("camera_bind_group_layout"),
             });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
+        let eye_cameras = [(); 2].map(|_| EyeCamera::new(&device, &camera_bind_group_layout));
 
-        let camera_controller = CameraController::new(2.0, 0.002);
+        let camera_controllers = create_camera_controllers();
+        let input_map = create_input_map(BTreeMap::new(), &camera_controllers);
 
         let frame_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("camera_buffer"),
@@ -407,16 +498,7 @@ impl State {
 
         let utils_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
+                entries: Group::Utils.entries(),
                 label: Some("utils_bind_group_layout"),
             });
 
@@ -429,111 +511,111 @@ impl State {
             label: Some("utils_bind_group"),
         });
 
-        let lights = vec![Light {
-            position: (5.0, 5.0, 5.0).into(),
-            colour: [1.0, 0.8, 0.8],
-            strength: 1.0,
-            radius: 1.0,
-        }];
-
-        let mut lights_uniform = Vec::new();
-        for light in &lights {
-            let mut uniform = LightUniform::new();
-            uniform.update_values(&light);
-            lights_uniform.push(uniform);
-        }
-
-        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("lights_buffer"),
-            contents: bytemuck::cast_slice(&lights_uniform),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
+        let lights_buffer = DynamicBuffer::new(
+            &device,
+            "lights_buffer",
+            bytemuck::cast_slice(&light_uniforms(&scene)),
+            wgpu::BufferUsages::STORAGE,
+        );
+        let sdf_buffer = DynamicBuffer::new(
+            &device,
+            "sdf_buffer",
+            bytemuck::cast_slice(&sdf_uniforms(&scene)),
+            wgpu::BufferUsages::STORAGE,
+        );
 
-        let lights_bind_group_layout =
+        let scene_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("lights_bind_group_layout"),
+                entries: Group::Scene.entries(),
+                label: Some("scene_bind_group_layout"),
             });
 
-        let lights_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &lights_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: lights_buffer.as_entire_binding(),
-            }],
-            label: Some("lights_bind_group"),
-        });
-
-        let fullscreen_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Fullscreen Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("fullscreen.wgsl").into()),
-        });
+        let scene_bind_group = create_scene_bind_group(
+            &device,
+            &scene_bind_group_layout,
+            &lights_buffer,
+            &sdf_buffer,
+        );
 
-        let fullscreen_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Fullscreen Pipeline Layout"),
-                bind_group_layouts: &[
-                    &fullscreen_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &utils_bind_group_layout,
-                    &lights_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
+        let fullscreen_pipeline_layout = create_pipeline_layout(
+            &device,
+            shaders::FULLSCREEN,
+            &[
+                (Group::Fullscreen, &fullscreen_bind_group_layout),
+                (Group::Camera, &camera_bind_group_layout),
+                (Group::Utils, &utils_bind_group_layout),
+                (Group::Scene, &scene_bind_group_layout),
+            ],
+        );
 
-        let fullscreen_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Fullscreen Render Pipeline"),
-            layout: Some(&fullscreen_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &fullscreen_shader,
-                entry_point: "vs_main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: (std::mem::size_of::<f32>() * 3) as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[wgpu::VertexAttribute {
-                        offset: 0,
-                        shader_location: 0,
-                        format: wgpu::VertexFormat::Float32x3,
-                    }],
-                }],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fullscreen_shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    // Final view
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        let mut failed_reloads = Vec::new();
+        let fullscreen_pipelines = build_shader(
+            &device,
+            shaders::FULLSCREEN,
+            dev_mode,
+            &mut failed_reloads,
+            |source| {
+                create_fullscreen_pipelines(
+                    &device,
+                    &fullscreen_pipeline_layout,
+                    source,
+                    config.format,
+                )
             },
-            multiview: None,
+        )?;
+
+        let error_overlay_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(shaders::ERROR_OVERLAY.name),
+            source: wgpu::ShaderSource::Wgsl(shaders::ERROR_OVERLAY.source(false)?.into()),
         });
 
+        let error_overlay_pipeline_layout =
+            create_pipeline_layout(&device, shaders::ERROR_OVERLAY, &[]);
+
+        let error_overlay_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Error Overlay Render Pipeline"),
+                layout: Some(&error_overlay_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &error_overlay_shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: (std::mem::size_of::<f32>() * 3) as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &[wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 0,
+                            format: wgpu::VertexFormat::Float32x3,
+                        }],
+                    }],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &error_overlay_shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            });
+
         let fullscreen_vertex_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Fullscreen Vertex Buffer"),
@@ -548,37 +630,6 @@ impl State {
                 usage: wgpu::BufferUsages::INDEX,
             });
 
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let position = cgmath::Vector3 {
-                        x: x as f32 * 3.0,
-                        y: 0.0,
-                        z: z as f32 * 3.0,
-                    } - INSTANCE_DISPLACEMENT;
-
-                    let rotation = if true {
-                        //position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
         let depth_texture = Texture::create_depth_texture(
             &device,
             wgpu::Extent3d {
@@ -589,112 +640,213 @@ impl State {
             "depth_texture",
         );
 
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: Group::Texture.entries(),
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let models = load_models(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            &scene_description.models,
+        )
+        .await?;
+        let (instance_data, mesh_draws) = instance_data(&scene, &models);
+        let instance_buffer = DynamicBuffer::new(
+            &device,
+            "Instance Buffer",
+            bytemuck::cast_slice(&instance_data),
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        let gbuffer_pipeline_layout = create_pipeline_layout(
+            &device,
+            shaders::GBUFFER,
+            &[
+                (Group::Texture, &texture_bind_group_layout),
+                (Group::Camera, &camera_bind_group_layout),
+            ],
+        );
+
+        let gbuffer_pipeline = build_shader(
+            &device,
+            shaders::GBUFFER,
+            dev_mode,
+            &mut failed_reloads,
+            |source| {
+                create_gbuffer_pipeline(&device, &gbuffer_pipeline_layout, source, config.format)
+            },
+        )?;
+
         let skybox_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("skybox_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        count: None,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        count: None,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        count: None,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                    },
-                ],
+                entries: Group::Skybox.entries(),
             });
 
-        let skybox_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("skybox_pipeline_layout"),
-                bind_group_layouts: &[&skybox_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+        let skybox_pipeline_layout = create_pipeline_layout(
+            &device,
+            shaders::SKYBOX,
+            &[(Group::Skybox, &skybox_bind_group_layout)],
+        );
 
-        let skybox_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("skybox_shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
-        });
+        let mut state = Self {
+            instance,
+            adapter,
+            target,
+            device,
+            queue,
+            config,
+            size,
+            camera,
+            camera_projection,
+            eye_cameras,
+            stereo,
+            lens,
+            focus_distance,
+            focus_probe,
+            camera_controllers,
+            active_controller: 0,
+            input_map,
+            focused: true,
+            quit_requested: false,
+            camera_playback: None,
+            track_recorder: None,
+            bookmarks,
+            bookmark_transition: 0.0,
+            bookmark_flight: None,
+            scene,
+            scene_description,
+            scene_path,
+            watcher,
+            failed_reloads,
+            error_overlay_pipeline,
+            screenshot_requests: Vec::new(),
+            pending_screenshots: Vec::new(),
+            camera_node,
+            models,
+            instance_buffer,
+            mesh_draws,
+            depth_texture,
+            texture_bind_group_layout,
+            gbuffer_pipeline_layout,
+            gbuffer_pipeline,
+            fullscreen_pipeline_layout,
+            fullscreen_pipelines,
+            fullscreen_bind_group_layout,
+            fullscreen_bind_group,
+            peel_depth_texture,
+            fullscreen_vertex_buffer,
+            fullscreen_index_buffer,
+            albedo_texture,
+            first_depth_texture,
+            position_texture,
+            normal_texture,
+            specular_texture,
+            emissive_texture,
+            last_frame_texture,
+            accumulation_texture,
+            accumulated_inputs: Vec::new(),
+            skybox_texture,
+            panorama_texture,
+            skybox_bind_group_layout,
+            skybox_pipeline_layout,
+            frame_count: 0.0,
+            frame_count_buffer,
+            utils_bind_group,
+            scene_bind_group_layout,
+            scene_bind_group,
+            lights_buffer,
+            sdf_buffer,
+        };
+        if let Err(e) = state.render_skybox(dev_mode) {
+            if !dev_mode {
+                return Err(e);
+            }
+            eprintln!("{:?}", e);
+            state.failed_reloads.push(shaders::SKYBOX.path());
+            state.render_skybox(false)?;
+        }
+        Ok(state)
+    }
 
-        let skybox_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("skybox_pipeline"),
-            layout: Some(&skybox_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &skybox_shader,
-                entry_point: "vs_main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: (std::mem::size_of::<f32>() * 3) as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[wgpu::VertexAttribute {
-                        offset: 0,
-                        shader_location: 0,
-                        format: wgpu::VertexFormat::Float32x3,
-                    }],
-                }],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &skybox_shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    // Final view
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+    /// Projects the environment panorama onto the faces of the skybox cubemap
+    fn render_skybox(&self, from_disk: bool) -> anyhow::Result<()> {
+        let skybox_pipeline = shaders::SKYBOX.build(&self.device, from_disk, |source| {
+            let skybox_shader = self
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(shaders::SKYBOX.name),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
 
-        let current_face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("current_face_buffer"),
-            contents: bytemuck::bytes_of(&0u32),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+            self.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("skybox_pipeline"),
+                    layout: Some(&self.skybox_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &skybox_shader,
+                        entry_point: "vs_main",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: (std::mem::size_of::<f32>() * 3) as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &[wgpu::VertexAttribute {
+                                offset: 0,
+                                shader_location: 0,
+                                format: wgpu::VertexFormat::Float32x3,
+                            }],
+                        }],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &skybox_shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            // Final view
+                            format: self.config.format,
+                            blend: Some(wgpu::BlendState::REPLACE),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face: wgpu::FrontFace::Ccw,
+                        cull_mode: None,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                        unclipped_depth: false,
+                        conservative: false,
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState {
+                        count: 1,
+                        mask: !0,
+                        alpha_to_coverage_enabled: false,
+                    },
+                    multiview: None,
+                })
+        })?;
+
+        let current_face_buffer =
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("current_face_buffer"),
+                    contents: bytemuck::bytes_of(&0u32),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
 
-        let skybox_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let skybox_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("skybox_bind_group"),
-            layout: &skybox_bind_group_layout,
+            layout: &self.skybox_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&panorama_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&self.panorama_texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&panorama_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&self.panorama_texture.sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -708,11 +860,14 @@ impl State {
         });
 
         for i in 0..6u32 {
-            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Skybox Render Encoder"),
-            });
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Skybox Render Encoder"),
+                });
             {
-                let view = &skybox_texture
+                let view = &self
+                    .skybox_texture
                     .texture
                     .create_view(&wgpu::TextureViewDescriptor {
                         base_array_layer: i,
@@ -740,51 +895,20 @@ impl State {
 
                 skybox_pass.set_pipeline(&skybox_pipeline);
                 skybox_pass.set_bind_group(0, &skybox_bind_group, &[]);
-                skybox_pass.set_vertex_buffer(0, fullscreen_vertex_buffer.slice(..));
-                skybox_pass
-                    .set_index_buffer(fullscreen_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                skybox_pass.set_vertex_buffer(0, self.fullscreen_vertex_buffer.slice(..));
+                skybox_pass.set_index_buffer(
+                    self.fullscreen_index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint16,
+                );
                 skybox_pass.draw_indexed(0..FULLSCREEN_INDICES.len() as u32, 0, 0..1);
             }
 
-            queue.write_buffer(&current_face_buffer, 0, bytemuck::bytes_of(&i));
-            queue.submit(std::iter::once(encoder.finish()));
+            self.queue
+                .write_buffer(&current_face_buffer, 0, bytemuck::bytes_of(&i));
+            self.queue.submit(std::iter::once(encoder.finish()));
         }
 
-        Self {
-            instance,
-            adapter,
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            camera,
-            camera_projection,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
-            camera_controller,
-            instances,
-            instance_buffer,
-            depth_texture,
-            mouse_pressed: false,
-            fullscreen_pipeline,
-            fullscreen_bind_group,
-            peel_depth_texture,
-            fullscreen_vertex_buffer,
-            fullscreen_index_buffer,
-            albedo_texture,
-            first_depth_texture,
-            position_texture,
-            normal_texture,
-            last_frame_texture,
-            frame_count: 0.0,
-            frame_count_buffer,
-            utils_bind_group,
-            lights_bind_group,
-            lights,
-            lights_buffer,
-        }
+        Ok(())
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -792,58 +916,132 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(texture) => {
+                    *texture = context::create_offscreen_texture(&self.device, &self.config)
+                }
+            }
             self.camera_projection
-                .resize(new_size.width, new_size.height);
-            self.depth_texture = Texture::create_depth_texture(
-                &self.device,
-                wgpu::Extent3d {
-                    width: self.config.width,
-                    height: self.config.height,
-                    depth_or_array_layers: 1,
-                },
-                "depth_texture",
-            );
+                .resize(self.stereo.eye_width(new_size.width), new_size.height);
+            let size = wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            };
+            self.depth_texture = Texture::create_depth_texture(&self.device, size, "depth_texture");
             self.peel_depth_texture = Texture::create_color_texture(
                 &self.device,
-                wgpu::Extent3d {
-                    width: self.config.width,
-                    height: self.config.height,
-                    depth_or_array_layers: 1,
-                },
+                size,
                 "peel_depth_texture",
+                wgpu::TextureFormat::Rgba16Float,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            self.albedo_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "albedo_texture",
+                self.config.format,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            self.position_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "position_texture",
+                wgpu::TextureFormat::Rgba32Float,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            self.normal_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "normal_texture",
                 wgpu::TextureFormat::Rgba32Float,
                 wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             );
+            self.specular_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "specular_texture",
+                wgpu::TextureFormat::Rgba16Float,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            self.emissive_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "emissive_texture",
+                wgpu::TextureFormat::Rgba16Float,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            );
+            self.last_frame_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "last_frame_texture",
+                ACCUMULATION_FORMAT,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            );
+            self.accumulation_texture = Texture::create_color_texture(
+                &self.device,
+                size,
+                "accumulation_texture",
+                ACCUMULATION_FORMAT,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            );
+            self.accumulated_inputs.clear();
+            self.fullscreen_bind_group = create_fullscreen_bind_group(
+                &self.device,
+                &self.fullscreen_bind_group_layout,
+                &[
+                    &self.albedo_texture,
+                    &self.peel_depth_texture,
+                    &self.position_texture,
+                    &self.normal_texture,
+                    &self.last_frame_texture,
+                    &self.skybox_texture,
+                    &self.specular_texture,
+                    &self.emissive_texture,
+                ],
+            );
         }
     }
 
     pub fn input<T>(&mut self, event: &Event<T>) -> bool {
         match event {
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion { delta } => {
-                    if self.mouse_pressed {
-                        self.camera_controller.process_mouse(delta.0, delta.1);
-                    }
-                    true
-                }
+            // Raw mouse motion arrives even when another window has focus
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if self.focused => {
+                self.camera_controller().process_mouse(delta.0, delta.1);
+                true
+            }
 
-                DeviceEvent::Key(KeyboardInput {
-                    virtual_keycode: Some(key),
-                    state,
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state,
+                            ..
+                        },
                     ..
-                }) => self.camera_controller.process_keyboard(*key, *state),
+                } => self.process_binding(Binding::Key(*key), *state),
 
-                _ => false,
-            },
+                WindowEvent::MouseInput { button, state, .. } => {
+                    self.process_binding(Binding::Mouse(*button), *state)
+                }
 
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::MouseInput {
-                    button: winit::event::MouseButton::Left,
-                    state,
-                    ..
-                } => {
-                    self.mouse_pressed = *state == ElementState::Pressed;
+                WindowEvent::MouseWheel { delta, .. } => {
+                    self.camera_controller().process_scroll(delta)
+                }
+
+                WindowEvent::Focused(focused) => {
+                    self.focused = *focused;
+                    // Nothing held now will be heard being released
+                    if !focused {
+                        for event in self.input_map.release_all() {
+                            self.process_action(event);
+                        }
+                    }
                     true
                 }
 
@@ -854,26 +1052,651 @@ impl State {
         }
     }
 
+    /// Replaces the default bindings of the actions `bindings` names
+    pub fn set_bindings(&mut self, bindings: BTreeMap<String, Vec<Binding>>) {
+        self.input_map = create_input_map(bindings, &self.camera_controllers);
+        for action in self.input_map.unknown_actions() {
+            eprintln!("Ignoring bindings for unknown action {:?}", action);
+        }
+    }
+
+    /// Whether the quit action has been pressed
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    fn process_binding(&mut self, binding: Binding, state: ElementState) -> bool {
+        let mut used = false;
+        for event in self.input_map.process(binding, state) {
+            used |= self.process_action(event);
+        }
+        used
+    }
+
+    fn process_action(&mut self, ActionEvent { action, pressed }: ActionEvent) -> bool {
+        if let Some(slot) = input::BOOKMARKS.iter().position(|&name| name == action) {
+            if pressed {
+                self.use_bookmark(slot as u32);
+            }
+            return true;
+        }
+        if action == input::STORE_BOOKMARK {
+            return true;
+        }
+        if !APP_ACTIONS.iter().any(|(name, _)| *name == action) {
+            return self.camera_controller().process_action(action, pressed);
+        }
+        if !pressed {
+            return true;
+        }
+        match action {
+            input::QUIT => self.quit_requested = true,
+            input::SAVE_SCENE => match self.save_scene() {
+                Ok(()) => println!("Saved scene to {:?}", self.scene_path),
+                Err(e) => eprintln!("{:?}", e),
+            },
+            input::RECORD_CAMERA_TRACK => self.toggle_track_recording(),
+            input::PLAY_CAMERA_TRACK => self.toggle_camera_playback(),
+            input::NEXT_CAMERA_TRACK => self.play_next_camera_track(),
+            input::SCREENSHOT => self.request_screenshot(capture::screenshot_path()),
+            input::NEXT_CAMERA_CONTROLLER => self.next_camera_controller(),
+            input::NEXT_PROJECTION => self.next_projection_mode(),
+            input::NEXT_STEREO_MODE => self.next_stereo_mode(),
+            _ => unreachable!("{} is missing from APP_ACTIONS", action),
+        }
+        true
+    }
+
+    /// Writes the scene, including the current camera pose, back to the file it came from
+    pub fn save_scene(&mut self) -> anyhow::Result<()> {
+        self.scene_description.update_nodes(&self.scene);
+        self.scene_description.save(&self.scene_path)
+    }
+
+    fn camera_controller(&mut self) -> &mut dyn CameraController {
+        self.camera_controllers[self.active_controller].as_mut()
+    }
+
+    /// Hands the camera over to the next controller, which carries on from where it is
+    pub fn next_camera_controller(&mut self) {
+        // Whatever's held carries over, rather than leaving the old controller moving forever
+        let held = self.input_map.held_actions();
+        for action in &held {
+            self.camera_controller().process_action(action, false);
+        }
+        self.active_controller = (self.active_controller + 1) % self.camera_controllers.len();
+        let camera = self.camera.clone();
+        let controller = self.camera_controller();
+        controller.activate(&camera);
+        for action in &held {
+            controller.process_action(action, true);
+        }
+        println!("Camera controller: {}", controller.name());
+    }
+
+    /// Cycles through the projection modes. Saving the scene keeps the new one.
+    pub fn next_projection_mode(&mut self) {
+        let modes = ProjectionMode::ALL;
+        let index = modes
+            .iter()
+            .position(|&mode| mode == self.camera_projection.mode)
+            .unwrap_or(0);
+        let mode = modes[(index + 1) % modes.len()];
+        self.set_projection_mode(mode);
+        println!("Projection: {:?}", mode);
+    }
+
+    pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+        self.camera_projection.mode = mode;
+        self.scene_description.projection.mode = mode;
+    }
+
+    /// Starts recording a camera track from the fly camera, or stops and adds the recorded track
+    /// to the scene. Saving the scene writes it to the file.
+    pub fn toggle_track_recording(&mut self) {
+        let pose = self.camera_pose();
+        match self.track_recorder.take() {
+            Some(recorder) => {
+                let track = recorder.finish(pose);
+                println!(
+                    "Recorded camera track {:?}, {:.1} seconds long",
+                    track.name,
+                    track.duration()
+                );
+                self.scene_description.camera_tracks.push(track);
+            }
+            None => {
+                let tracks = &self.scene_description.camera_tracks;
+                let name = (1..)
+                    .map(|n| format!("track_{}", n))
+                    .find(|name| tracks.iter().all(|track| &track.name != name))
+                    .unwrap();
+                println!("Recording camera track {:?}", name);
+                self.track_recorder = Some(TrackRecorder::new(name, pose));
+            }
+        }
+    }
+
+    /// Pauses or resumes the current camera track, starting the first one if none has been played
+    /// and restarting a track that's finished
+    pub fn toggle_camera_playback(&mut self) {
+        let tracks = &self.scene_description.camera_tracks;
+        match &mut self.camera_playback {
+            Some(playback) if playback.playing => {
+                playback.playing = false;
+                self.return_camera_to_controller();
+            }
+            // Reloading the scene file can remove the track
+            Some(playback) if playback.track < tracks.len() => {
+                let track = &tracks[playback.track];
+                if playback.time >= track.duration() && !track.looping {
+                    playback.time = 0.0;
+                }
+                playback.playing = true;
+                self.bookmark_flight = None;
+            }
+            _ => self.play_camera_track_at(0),
+        }
+    }
+
+    /// Plays the track after the current one from its start
+    pub fn play_next_camera_track(&mut self) {
+        let next = self
+            .camera_playback
+            .as_ref()
+            .map_or(0, |playback| playback.track + 1);
+        self.play_camera_track_at(next);
+    }
+
+    /// Plays the camera track called `name` from its start, returning its duration
+    pub fn play_camera_track(&mut self, name: &str) -> anyhow::Result<f32> {
+        let tracks = &self.scene_description.camera_tracks;
+        let index = tracks
+            .iter()
+            .position(|track| track.name == name)
+            .with_context(|| format!("No camera track called {:?}", name))?;
+        let duration = tracks[index].duration();
+        self.play_camera_track_at(index);
+        Ok(duration)
+    }
+
+    /// Wraps around past the last track
+    fn play_camera_track_at(&mut self, index: usize) {
+        let tracks = &self.scene_description.camera_tracks;
+        if tracks.is_empty() {
+            println!("The scene has no camera tracks");
+            return;
+        }
+        let index = index % tracks.len();
+        println!("Playing camera track {:?}", tracks[index].name);
+        self.bookmark_flight = None;
+        self.camera_playback = Some(CameraPlayback {
+            track: index,
+            time: 0.0,
+            playing: true,
+        });
+        self.advance_camera_playback(0.0);
+    }
+
+    /// Moves the camera along the playing track. Returns false if no track is playing, so the fly
+    /// controls are in charge.
+    fn advance_camera_playback(&mut self, dt: f32) -> bool {
+        let playback = match &mut self.camera_playback {
+            Some(playback) if playback.playing => playback,
+            _ => return false,
+        };
+        // Reloading the scene file can remove the track
+        let track = match self.scene_description.camera_tracks.get(playback.track) {
+            Some(track) => track,
+            None => {
+                self.camera_playback = None;
+                self.return_camera_to_controller();
+                return false;
+            }
+        };
+        playback.time += dt;
+        let finished = playback.time >= track.duration() && !track.looping;
+        if finished {
+            playback.playing = false;
+        }
+        if let Some(pose) = track.sample(playback.time) {
+            self.set_camera_pose(pose);
+        }
+        if finished {
+            self.return_camera_to_controller();
+        }
+        true
+    }
+
+    /// Hands the camera back to the active controller after a track or bookmark has moved it, so
+    /// the controller carries on from there rather than from wherever it last was
+    fn return_camera_to_controller(&mut self) {
+        let camera = self.camera.clone();
+        self.camera_controller().activate(&camera);
+    }
+
+    fn camera_pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.camera.position,
+            yaw: self.camera.yaw.into(),
+            pitch: self.camera.pitch.into(),
+            roll: self.camera.roll.into(),
+            fov: self.camera_projection.fovy().into(),
+        }
+    }
+
+    fn set_camera_pose(&mut self, pose: CameraPose) {
+        self.camera = Camera::new(pose.position, pose.yaw, pose.pitch);
+        self.camera.roll = pose.roll.into();
+        self.camera_projection.set_fovy(pose.fov);
+        self.move_camera_node();
+    }
+
+    /// Seconds recalling a bookmark takes to move the camera there, or zero to jump
+    pub fn set_bookmark_transition(&mut self, seconds: f32) {
+        self.bookmark_transition = seconds.max(0.0);
+    }
+
+    /// Stores the view in bookmark `slot` while `input::STORE_BOOKMARK` is held, and recalls it
+    /// otherwise
+    fn use_bookmark(&mut self, slot: u32) {
+        if self
+            .input_map
+            .held_actions()
+            .contains(&input::STORE_BOOKMARK)
+        {
+            let bookmark = Bookmark::from_pose(self.camera_pose());
+            match self.bookmarks.store(slot, bookmark) {
+                Ok(()) => println!("Stored bookmark {} in {:?}", slot, self.bookmarks.path()),
+                Err(e) => eprintln!("{:?}", e),
+            }
+        } else if let Err(e) = self.recall_bookmark(slot) {
+            println!("{}", e);
+        }
+    }
+
+    /// Moves the camera to bookmark `slot`, taking over from any camera track that's playing
+    pub fn recall_bookmark(&mut self, slot: u32) -> anyhow::Result<()> {
+        let pose = self
+            .bookmarks
+            .get(slot)
+            .with_context(|| format!("No bookmark {} in {:?}", slot, self.bookmarks.path()))?
+            .pose();
+        if let Some(playback) = &mut self.camera_playback {
+            playback.playing = false;
+        }
+        let track = CameraTrack::between(
+            format!("bookmark {}", slot),
+            self.camera_pose(),
+            pose,
+            self.bookmark_transition,
+        );
+        self.bookmark_flight = Some((track, 0.0));
+        self.advance_bookmark_flight(0.0);
+        Ok(())
+    }
+
+    /// Moves the camera towards the bookmark being recalled. Returns false if there isn't one, so
+    /// the camera controller is in charge.
+    fn advance_bookmark_flight(&mut self, dt: f32) -> bool {
+        let (track, time) = match &mut self.bookmark_flight {
+            Some(flight) => flight,
+            None => return false,
+        };
+        *time += dt;
+        let arrived = *time >= track.duration();
+        if let Some(pose) = track.sample(*time) {
+            self.set_camera_pose(pose);
+        }
+        if arrived {
+            self.bookmark_flight = None;
+            self.return_camera_to_controller();
+        }
+        true
+    }
+
+    /// Saves the next frame rendered to `path`, as a PNG or EXR by its extension. The frame is
+    /// copied back in the background and saved by a later `update`.
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.screenshot_requests.push(path);
+    }
+
+    pub fn lens(&self) -> &Lens {
+        &self.lens
+    }
+
+    /// Replaces the lens, which saving the scene keeps
+    pub fn set_lens(&mut self, lens: Lens) {
+        if let Some(distance) = lens.focus_distance {
+            self.focus_distance = distance;
+        }
+        self.scene_description.lens = lens.clone();
+        self.lens = lens;
+    }
+
+    pub fn stereo(&self) -> &Stereo {
+        &self.stereo
+    }
+
+    /// Replaces the stereo settings, which saving the scene keeps
+    pub fn set_stereo(&mut self, stereo: Stereo) {
+        self.camera_projection
+            .resize(stereo.eye_width(self.config.width), self.config.height);
+        self.scene_description.stereo = stereo.clone();
+        self.stereo = stereo;
+    }
+
+    /// Cycles through the stereo modes, keeping the eyes' spacing and convergence
+    pub fn next_stereo_mode(&mut self) {
+        let modes = StereoMode::ALL;
+        let index = modes
+            .iter()
+            .position(|&mode| mode == self.stereo.mode)
+            .unwrap_or(0);
+        let mode = modes[(index + 1) % modes.len()];
+        self.set_stereo(Stereo {
+            mode,
+            ..self.stereo.clone()
+        });
+        println!("Stereo: {:?}", mode);
+    }
+
+    /// Where `eye` is drawn in the frame, as x, y, width and height
+    fn eye_viewport(&self, eye: usize) -> [f32; 4] {
+        let width = self.stereo.eye_width(self.config.width);
+        let x = match self.stereo.mode {
+            StereoMode::SideBySide => eye as u32 * width,
+            _ => 0,
+        };
+        [x, 0, width, self.config.height].map(|value| value as f32)
+    }
+
+    fn fullscreen_pipeline(&self, eye: usize) -> &wgpu::RenderPipeline {
+        match self.stereo.mode {
+            StereoMode::Anaglyph => &self.fullscreen_pipelines[1 + eye],
+            _ => &self.fullscreen_pipelines[0],
+        }
+    }
+
+    fn autofocus(&self) -> bool {
+        self.lens.aperture > 0.0 && self.lens.focus_distance.is_none()
+    }
+
+    /// Focuses on `distance`, measured under the centre of the screen, unless it's nearly where
+    /// the lens is focused already or nothing's there. The probe measures zero when the camera
+    /// is touching or inside an object, so distances are kept beyond the near plane.
+    fn refocus(&mut self, distance: f32) -> bool {
+        let distance = distance.max(self.camera_projection.znear());
+        let moved =
+            (distance - self.focus_distance).abs() > self.focus_distance * REFOCUS_THRESHOLD;
+        if !self.autofocus() || !moved || distance >= MAXIMUM_TRACE_DISTANCE {
+            return false;
+        }
+        self.focus_distance = distance;
+        true
+    }
+
+    /// Waits for autofocus to measure the last frame rendered, and returns whether it refocused
+    pub fn wait_for_autofocus(&mut self) -> bool {
+        match self.focus_probe.wait(&self.device) {
+            Some(distance) => self.refocus(distance),
+            None => false,
+        }
+    }
+
+    /// Saves the screenshots whose frames have arrived from the GPU, on threads of their own so
+    /// encoding doesn't hold up rendering
+    fn save_finished_screenshots(&mut self) {
+        if self.pending_screenshots.is_empty() {
+            return;
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        self.pending_screenshots.retain(|(readback, path)| {
+            let image = match readback.try_finish() {
+                Some(image) => image,
+                None => return true,
+            };
+            let path = path.clone();
+            std::thread::spawn(move || {
+                match image.and_then(|image| capture::save(&image, &path)) {
+                    Ok(()) => println!("Saved screenshot to {:?}", path),
+                    Err(e) => eprintln!("Couldn't save screenshot {:?}: {:?}", path, e),
+                }
+            });
+            false
+        });
+    }
+
+    /// Moves the camera to `position` and points it at `target`. Either can be left out to keep
+    /// the camera's current position or direction.
+    pub fn override_camera(
+        &mut self,
+        position: Option<cgmath::Point3<f32>>,
+        target: Option<cgmath::Point3<f32>>,
+    ) -> anyhow::Result<()> {
+        let position = position.unwrap_or(self.camera.position);
+        let direction = match target {
+            Some(target) => target - position,
+            None => self.camera.direction(),
+        };
+        anyhow::ensure!(
+            direction.magnitude2() > 0.0,
+            "The camera can't look at its own position"
+        );
+        self.camera = Camera::looking_along(position, direction);
+        self.move_camera_node();
+        Ok(())
+    }
+
+    /// Places the scene's camera node at the fly camera's pose
+    fn move_camera_node(&mut self) {
+        // The fly camera works in world space, so the node it drives can't have a parent
+        if self.scene.node(self.camera_node).parent().is_some() {
+            self.scene.set_parent(self.camera_node, None).unwrap();
+        }
+        let camera_node = self.scene.node_mut(self.camera_node);
+        camera_node.transform.position = self.camera.position.to_vec();
+        camera_node.transform.rotation = self.camera.rotation();
+    }
+
+    fn reload_changed_files(&mut self) {
+        let changed = match &self.watcher {
+            Some(watcher) => watcher.changed(),
+            None => return,
+        };
+        for path in changed {
+            if shaders::INCLUDES
+                .iter()
+                .any(|include| include.path() == path)
+            {
+                // Any shader could include the file, so they're all rebuilt
+                for shader in shaders::SHADERS {
+                    let result = self.rebuild_shader(shader);
+                    self.record_reload(shader.path(), result);
+                }
+            } else if let Some(&shader) = shaders::SHADERS.iter().find(|s| s.path() == path) {
+                let result = self.rebuild_shader(shader);
+                self.record_reload(path, result);
+            } else {
+                let result = self.reload_scene();
+                self.record_reload(path, result);
+            }
+        }
+    }
+
+    fn record_reload(&mut self, path: PathBuf, result: anyhow::Result<()>) {
+        self.failed_reloads.retain(|failed| *failed != path);
+        self.accumulated_inputs.clear();
+        match result {
+            Ok(()) => println!("Reloaded {:?}", path),
+            Err(e) => {
+                eprintln!("Couldn't reload {:?}: {:?}", path, e);
+                self.failed_reloads.push(path);
+            }
+        }
+    }
+
+    /// Rebuilds `shader` from disk, keeping the old pipeline if it doesn't build
+    fn rebuild_shader(&mut self, shader: shaders::ShaderFile) -> anyhow::Result<()> {
+        if shader == shaders::FULLSCREEN {
+            self.fullscreen_pipelines = shader.build(&self.device, true, |source| {
+                create_fullscreen_pipelines(
+                    &self.device,
+                    &self.fullscreen_pipeline_layout,
+                    source,
+                    self.config.format,
+                )
+            })?;
+        } else if shader == shaders::GBUFFER {
+            self.gbuffer_pipeline = shader.build(&self.device, true, |source| {
+                create_gbuffer_pipeline(
+                    &self.device,
+                    &self.gbuffer_pipeline_layout,
+                    source,
+                    self.config.format,
+                )
+            })?;
+        } else {
+            self.render_skybox(true)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the scene from its file, keeping the current view. Models and the skybox are only
+    /// reloaded if their descriptions changed. Nothing is replaced unless everything loads, and
+    /// the scene's bookmarks are read again along with it.
+    fn reload_scene(&mut self) -> anyhow::Result<()> {
+        let description = SceneDescription::load(&self.scene_path)?;
+        let mut scene = description.build_scene()?;
+        scene.update_world_transforms();
+
+        let models = if description.models != self.scene_description.models {
+            Some(pollster::block_on(load_models(
+                &self.device,
+                &self.queue,
+                &self.texture_bind_group_layout,
+                &description.models,
+            ))?)
+        } else {
+            None
+        };
+        let panorama_texture = if description.environment != self.scene_description.environment {
+            Some(pollster::block_on(load_panorama(
+                &self.device,
+                &self.queue,
+                &description.environment,
+            ))?)
+        } else {
+            None
+        };
+
+        if let Some(models) = models {
+            self.models = models;
+        }
+        if let Some(panorama_texture) = panorama_texture {
+            self.panorama_texture = panorama_texture;
+            self.render_skybox(true)?;
+        }
+        // Left alone otherwise, so a camera track's field of view isn't reset
+        if description.projection != self.scene_description.projection {
+            description.projection.apply(&mut self.camera_projection);
+        }
+        if description.lens != self.scene_description.lens {
+            self.set_lens(description.lens.clone());
+        }
+        if description.stereo != self.scene_description.stereo {
+            self.set_stereo(description.stereo.clone());
+        }
+        self.camera_node = first_camera(&mut scene);
+        let file_camera = camera_from_node(&scene, self.camera_node);
+        self.scene = scene;
+        self.scene_description = description;
+        self.bookmarks = Bookmarks::load_or_empty(&self.scene_path);
+        if file_camera != self.camera {
+            self.move_camera_node();
+        }
+        Ok(())
+    }
+
     pub fn update(&mut self, dt: std::time::Duration) {
-        let mut lights_uniform = Vec::new();
-        for light in &self.lights {
-            let mut uniform = LightUniform::new();
-            uniform.update_values(&light);
-            lights_uniform.push(uniform);
+        self.reload_changed_files();
+        self.save_finished_screenshots();
+        if let Some(distance) = self.focus_probe.try_finish() {
+            self.refocus(distance);
         }
-        self.queue.write_buffer(
-            &self.lights_buffer,
-            0,
-            bytemuck::cast_slice(&*lights_uniform),
+
+        let moved_automatically = self.advance_bookmark_flight(dt.as_secs_f32())
+            || self.advance_camera_playback(dt.as_secs_f32());
+        if !moved_automatically {
+            let previous_camera = self.camera.clone();
+            self.camera_controllers[self.active_controller].update_camera(&mut self.camera, dt);
+            // Only written back when it moves, so an untouched scene saves exactly as it was loaded
+            if self.camera != previous_camera {
+                self.move_camera_node();
+            }
+        }
+        if self.track_recorder.is_some() {
+            let pose = self.camera_pose();
+            if let Some(recorder) = &mut self.track_recorder {
+                recorder.update(dt.as_secs_f32(), pose);
+            }
+        }
+        self.scene.update_world_transforms();
+
+        let lights_resized = self.lights_buffer.write(
+            &self.device,
+            &self.queue,
+            bytemuck::cast_slice(&light_uniforms(&self.scene)),
         );
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform
-            .update_view_proj(&self.camera, &self.camera_projection);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
+        let sdf_resized = self.sdf_buffer.write(
+            &self.device,
+            &self.queue,
+            bytemuck::cast_slice(&sdf_uniforms(&self.scene)),
+        );
+        if lights_resized || sdf_resized {
+            self.scene_bind_group = create_scene_bind_group(
+                &self.device,
+                &self.scene_bind_group_layout,
+                &self.lights_buffer,
+                &self.sdf_buffer,
+            );
+        }
+
+        let (instance_data, mesh_draws) = instance_data(&self.scene, &self.models);
+        self.instance_buffer.write(
+            &self.device,
+            &self.queue,
+            bytemuck::cast_slice(&instance_data),
         );
+        self.mesh_draws = mesh_draws;
+
+        // Anything changing starts the average over, beginning from the centre of the lens
+        let eyes = self.stereo.eyes();
+        let pinhole = ViewOffset::centre(self.focus_distance);
+        let mut camera_uniforms =
+            eye_uniforms(&self.camera, &self.camera_projection, &eyes, pinhole);
+        let inputs = [
+            bytemuck::cast_slice(&camera_uniforms),
+            bytemuck::cast_slice(&light_uniforms(&self.scene)),
+            bytemuck::cast_slice(&sdf_uniforms(&self.scene)),
+            bytemuck::cast_slice(&instance_data),
+        ]
+        .concat();
+        if inputs != self.accumulated_inputs || self.lens.aperture <= 0.0 {
+            self.accumulated_inputs = inputs;
+            self.frame_count = 0.0;
+        }
+        if self.frame_count > 0.0 {
+            let lens = ViewOffset {
+                offset: self.lens.sample(self.frame_count as u32),
+                distance: self.focus_distance,
+            };
+            camera_uniforms = eye_uniforms(&self.camera, &self.camera_projection, &eyes, lens);
+        }
+        for (eye_camera, uniform) in self.eye_cameras.iter().zip(&camera_uniforms) {
+            self.queue
+                .write_buffer(&eye_camera.buffer, 0, bytemuck::bytes_of(uniform));
+        }
         self.queue.write_buffer(
             &self.frame_count_buffer,
             0,
@@ -882,50 +1705,685 @@ impl State {
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let output = match &self.target {
+            RenderTarget::Surface(surface) => Some(surface.get_current_texture()?),
+            RenderTarget::Offscreen(_) => None,
+        };
+        let texture = match (&output, &self.target) {
+            (Some(output), _) => &output.texture,
+            (None, RenderTarget::Offscreen(texture)) => texture,
+            (None, RenderTarget::Surface(_)) => unreachable!(),
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        {
-            let mut fullscreen_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Fullscreen Render Pass"),
+        // Each eye is drawn and lit in turn, with the G-buffer cleared in between, so anaglyph eyes
+        // can share the same pixels
+        for eye in 0..self.stereo.eyes().len() {
+            let eye_camera = &self.eye_cameras[eye].bind_group;
+            let [x, y, width, height] = self.eye_viewport(eye);
+            // Later eyes add to the frame rather than clearing it
+            let frame_load = |colour| match eye {
+                0 => wgpu::LoadOp::Clear(colour),
+                _ => wgpu::LoadOp::Load,
+            };
+            {
+                let clear = wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                };
+                let mut gbuffer_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("G-Buffer Render Pass"),
+                    color_attachments: &[
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.albedo_texture.view,
+                            resolve_target: None,
+                            ops: clear,
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.position_texture.view,
+                            resolve_target: None,
+                            ops: clear,
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.normal_texture.view,
+                            resolve_target: None,
+                            ops: clear,
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.specular_texture.view,
+                            resolve_target: None,
+                            ops: clear,
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.emissive_texture.view,
+                            resolve_target: None,
+                            ops: clear,
+                        }),
+                    ],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+
+                gbuffer_pass.set_pipeline(&self.gbuffer_pipeline);
+                gbuffer_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                gbuffer_pass.set_bind_group(1, eye_camera, &[]);
+                // Meshes can only be drawn through a projection matrix, so views that aren't linear
+                // leave the G-buffer empty
+                let mesh_draws = match self.camera_projection.mode.is_linear() {
+                    true => self.mesh_draws.as_slice(),
+                    false => &[],
+                };
+                // An empty buffer can't be bound, and there's nothing to draw anyway
+                if !mesh_draws.is_empty() {
+                    gbuffer_pass.set_vertex_buffer(1, self.instance_buffer.buffer.slice(..));
+                }
+                for draw in mesh_draws {
+                    let model = &self.models[draw.model];
+                    let mesh = &model.meshes[draw.mesh];
+                    let material = &model.materials[mesh.material];
+                    gbuffer_pass.set_bind_group(0, &material.bind_group, &[]);
+                    gbuffer_pass.draw_mesh_instanced(mesh, draw.instances.clone());
+                }
+            }
+            {
+                let mut fullscreen_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Fullscreen Render Pass"),
+                    color_attachments: &[
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: frame_load(wgpu::Color {
+                                    r: 0.1,
+                                    g: 0.2,
+                                    b: 0.3,
+                                    a: 1.0,
+                                }),
+                                store: true,
+                            },
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: &self.accumulation_texture.view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: frame_load(wgpu::Color::TRANSPARENT),
+                                store: true,
+                            },
+                        }),
+                    ],
+                    depth_stencil_attachment: None,
+                });
+
+                fullscreen_pass.set_pipeline(self.fullscreen_pipeline(eye));
+                fullscreen_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                fullscreen_pass.set_bind_group(0, &self.fullscreen_bind_group, &[]);
+                fullscreen_pass.set_bind_group(1, eye_camera, &[]);
+                fullscreen_pass.set_bind_group(2, &self.utils_bind_group, &[]);
+                fullscreen_pass.set_bind_group(3, &self.scene_bind_group, &[]);
+                fullscreen_pass.set_vertex_buffer(0, self.fullscreen_vertex_buffer.slice(..));
+                fullscreen_pass.set_index_buffer(
+                    self.fullscreen_index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint16,
+                );
+                fullscreen_pass.draw_indexed(0..FULLSCREEN_INDICES.len() as u32, 0, 0..1);
+            }
+        }
+        // Only a lens accumulates frames
+        if self.lens.aperture > 0.0 {
+            encoder.copy_texture_to_texture(
+                self.accumulation_texture.texture.as_image_copy(),
+                self.last_frame_texture.texture.as_image_copy(),
+                wgpu::Extent3d {
+                    width: self.config.width,
+                    height: self.config.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        if !self.failed_reloads.is_empty() {
+            let mut error_overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Error Overlay Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Load,
                         store: true,
                     },
                 })],
                 depth_stencil_attachment: None,
             });
 
-            fullscreen_pass.set_pipeline(&self.fullscreen_pipeline);
-            fullscreen_pass.set_bind_group(0, &self.fullscreen_bind_group, &[]);
-            fullscreen_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            fullscreen_pass.set_bind_group(2, &self.utils_bind_group, &[]);
-            fullscreen_pass.set_bind_group(3, &self.lights_bind_group, &[]);
-            fullscreen_pass.set_vertex_buffer(0, self.fullscreen_vertex_buffer.slice(..));
-            fullscreen_pass.set_index_buffer(
+            error_overlay_pass.set_pipeline(&self.error_overlay_pipeline);
+            error_overlay_pass.set_vertex_buffer(0, self.fullscreen_vertex_buffer.slice(..));
+            error_overlay_pass.set_index_buffer(
                 self.fullscreen_index_buffer.slice(..),
                 wgpu::IndexFormat::Uint16,
             );
-            fullscreen_pass.draw_indexed(0..FULLSCREEN_INDICES.len() as u32, 0, 0..1);
+            error_overlay_pass.draw_indexed(0..FULLSCREEN_INDICES.len() as u32, 0, 0..1);
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        // Measured through the centre of the lens, so the distance holds still with the view
+        if self.autofocus() && self.frame_count == 0.0 {
+            let [x, y, width, height] = self.eye_viewport(0);
+            self.focus_probe.start(
+                &self.device,
+                &self.queue,
+                &self.accumulation_texture.texture,
+                wgpu::Origin3d {
+                    x: (x + width / 2.0) as u32,
+                    y: (y + height / 2.0) as u32,
+                    z: 0,
+                },
+            );
+        }
+        for path in self.screenshot_requests.drain(..) {
+            match capture::Readback::start(&self.device, &self.queue, texture, &self.config) {
+                Ok(readback) => self.pending_screenshots.push((readback, path)),
+                Err(e) => eprintln!("Couldn't take screenshot {:?}: {:?}", path, e),
+            }
+        }
+        if let Some(output) = output {
+            output.present();
+        }
         self.frame_count += 1.0;
         Ok(())
     }
+
+    /// Copies the last frame rendered to an offscreen target back from the GPU
+    pub fn read_frame(&self) -> anyhow::Result<image::DynamicImage> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => {
+                capture::read_texture(&self.device, &self.queue, texture, &self.config)
+            }
+            RenderTarget::Surface(_) => {
+                anyhow::bail!("Frames can only be read back from offscreen targets")
+            }
+        }
+    }
+}
+
+/// Builds from the shader on disk in development mode. If that copy is broken the compiled in one
+/// is used instead, so the app can still start, and the shader is marked as failed.
+fn build_shader<T>(
+    device: &wgpu::Device,
+    shader: shaders::ShaderFile,
+    dev_mode: bool,
+    failed_reloads: &mut Vec<PathBuf>,
+    build: impl Fn(&str) -> T,
+) -> anyhow::Result<T> {
+    if dev_mode {
+        match shader.build(device, true, &build) {
+            Ok(built) => return Ok(built),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                failed_reloads.push(shader.path());
+            }
+        }
+    }
+    shader.build(device, false, build)
+}
+
+/// Builds a pipeline writing each of `FULLSCREEN_CHANNELS`
+fn create_fullscreen_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    source: &str,
+    format: wgpu::TextureFormat,
+) -> Vec<wgpu::RenderPipeline> {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(shaders::FULLSCREEN.name),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    FULLSCREEN_CHANNELS
+        .iter()
+        .map(|&channels| create_fullscreen_pipeline(device, layout, &shader, format, channels))
+        .collect()
+}
+
+fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    channels: wgpu::ColorWrites,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Fullscreen Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: (std::mem::size_of::<f32>() * 3) as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &[wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                }],
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[
+                Some(wgpu::ColorTargetState {
+                    // Final view
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: channels,
+                }),
+                Some(wgpu::ColorTargetState {
+                    // Accumulation, which float formats this precise can't blend
+                    format: ACCUMULATION_FORMAT,
+                    blend: None,
+                    write_mask: channels,
+                }),
+            ],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+fn create_gbuffer_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    source: &str,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(shaders::GBUFFER.name),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("G-Buffer Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[
+                Some(wgpu::ColorTargetState {
+                    // Albedo
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    // Position
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    // Normal
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    // Specular
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    // Emissive
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// Binds each texture's view and sampler to consecutive binding pairs, in order
+fn create_fullscreen_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    textures: &[&Texture],
+) -> wgpu::BindGroup {
+    let entries = textures
+        .iter()
+        .enumerate()
+        .flat_map(|(i, texture)| {
+            [
+                wgpu::BindGroupEntry {
+                    binding: i as u32 * 2,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: i as u32 * 2 + 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &entries,
+        label: Some("fullscreen_bind_group"),
+    })
+}
+
+fn create_scene_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    lights_buffer: &DynamicBuffer,
+    sdf_buffer: &DynamicBuffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: lights_buffer.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: sdf_buffer.buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("scene_bind_group"),
+    })
+}
+
+/// Storage buffers can't be empty, so a scene without lights gets a single zero strength light
+fn light_uniforms(scene: &Scene) -> Vec<LightUniform> {
+    let mut uniforms = scene.light_uniforms();
+    if uniforms.is_empty() {
+        uniforms.push(LightUniform::new());
+    }
+    uniforms
+}
+
+/// As with lights, an empty scene gets a placeholder, which the shader skips as it has no shape
+fn sdf_uniforms(scene: &Scene) -> Vec<SdfObjectUniform> {
+    let mut uniforms = scene.sdf_uniforms();
+    if uniforms.is_empty() {
+        uniforms.push(SdfObjectUniform::new());
+    }
+    uniforms
+}
+
+/// Instance data for every mesh of every model node in the scene, along with the draws using it.
+/// Each mesh gets its own run of instances, placed by its node in the model's own hierarchy.
+fn instance_data(scene: &Scene, models: &[model::Model]) -> (Vec<InstanceRaw>, Vec<MeshDraw>) {
+    let mut instance_data = Vec::new();
+    let mut mesh_draws = Vec::new();
+    for (model_index, model) in models.iter().enumerate() {
+        let instances = scene.model_instances(model_index).collect::<Vec<_>>();
+        if instances.is_empty() {
+            continue;
+        }
+        for (mesh, mesh_transform) in model.mesh_transforms() {
+            let start = instance_data.len() as u32;
+            instance_data.extend(
+                instances
+                    .iter()
+                    .map(|&transform| InstanceRaw::new(transform * mesh_transform)),
+            );
+            mesh_draws.push(MeshDraw {
+                model: model_index,
+                mesh,
+                instances: start..instance_data.len() as u32,
+            });
+        }
+    }
+    (instance_data, mesh_draws)
+}
+
+/// Puts `shader`'s pipeline layout together in the order `bindings::pipeline_layout` gives, from
+/// `bind_group_layouts`, which must have each of its groups
+fn create_pipeline_layout(
+    device: &wgpu::Device,
+    shader: shaders::ShaderFile,
+    bind_group_layouts: &[(Group, &wgpu::BindGroupLayout)],
+) -> wgpu::PipelineLayout {
+    let layouts: Vec<_> = bindings::pipeline_layout(shader)
+        .iter()
+        .map(|group| {
+            bind_group_layouts
+                .iter()
+                .find(|(name, _)| name == group)
+                .map(|&(_, layout)| layout)
+                .unwrap_or_else(|| panic!("{} needs the {:?} layout", shader.name, group))
+        })
+        .collect();
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(&format!("{} Pipeline Layout", shader.name)),
+        bind_group_layouts: &layouts,
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_camera_controllers() -> Vec<Box<dyn CameraController>> {
+    vec![
+        Box::new(FlyController::new(2.0, 0.002)),
+        Box::new(OrbitController::new(5.0, 0.002)),
+        Box::new(FlightController::new(2.0, 0.002, 1.5)),
+    ]
+}
+
+/// Registers the app's actions and every camera controller's, using `bindings` over their defaults
+fn create_input_map(
+    bindings: BTreeMap<String, Vec<Binding>>,
+    camera_controllers: &[Box<dyn CameraController>],
+) -> InputMap {
+    let mut input_map = InputMap::new(bindings);
+    for (action, defaults) in APP_ACTIONS {
+        input_map.register(action, defaults);
+    }
+    input_map.register(
+        input::STORE_BOOKMARK,
+        &[Binding::Key(VirtualKeyCode::RControl)],
+    );
+    for (action, key) in input::BOOKMARKS.iter().zip(BOOKMARK_KEYS) {
+        input_map.register(action, &[Binding::Key(key)]);
+    }
+    for controller in camera_controllers {
+        controller.register_actions(&mut input_map);
+    }
+    input_map
+}
+
+/// Returns the first camera in the scene, which the view is attached to, adding one if needed
+fn first_camera(scene: &mut Scene) -> NodeId {
+    let first_camera = scene
+        .nodes()
+        .find(|(_, node)| matches!(node.kind, NodeKind::Camera))
+        .map(|(id, _)| id);
+    first_camera
+        .unwrap_or_else(|| scene.add(None, "camera", Transform::default(), NodeKind::Camera))
+}
+
+/// The camera uniform for each of `eyes`, looking through the point `lens` on the lens
+fn eye_uniforms(
+    camera: &Camera,
+    projection: &Projection,
+    eyes: &[ViewOffset],
+    lens: ViewOffset,
+) -> Vec<CameraUniform> {
+    eyes.iter()
+        .map(|&eye| {
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(camera, projection, eye, lens);
+            uniform
+        })
+        .collect()
+}
+
+fn camera_from_node(scene: &Scene, id: NodeId) -> Camera {
+    let transform = scene.node(id).world_transform();
+    Camera::from_axes(
+        cgmath::Point3::from_vec(transform.w.truncate()),
+        transform.x.truncate(),
+        transform.y.truncate(),
+    )
+}
+
+async fn load_panorama(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    environment: &EnvironmentDescription,
+) -> anyhow::Result<Texture> {
+    match &environment.skybox {
+        Some(file_name) => resources::load_texture(file_name, false, device, queue).await,
+        None => Texture::from_colour(
+            device,
+            queue,
+            environment.background_bytes(),
+            "background_texture",
+            false,
+        ),
+    }
+}
+
+async fn load_models(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    descriptions: &[ModelDescription],
+) -> anyhow::Result<Vec<model::Model>> {
+    let mut models = Vec::new();
+    for description in descriptions {
+        let mut model = if description.file.ends_with(".gltf") || description.file.ends_with(".glb")
+        {
+            resources::load_gltf(&description.file, device, queue, layout).await?
+        } else {
+            resources::load_model(
+                &description.file,
+                description.normals,
+                device,
+                queue,
+                layout,
+            )
+            .await?
+        };
+        for material in &mut model.materials {
+            if let Some(properties) = description.materials.get(&material.name) {
+                material.set_properties(queue, *properties);
+            }
+        }
+        models.push(model);
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_action_is_registered() {
+        // Binding every action in the config file marks any that nothing registers as unknown
+        let bindings = input::ALL
+            .iter()
+            .chain(&input::BOOKMARKS)
+            .map(|action| (action.to_string(), Vec::new()))
+            .collect();
+        let input_map = create_input_map(bindings, &create_camera_controllers());
+        assert_eq!(
+            input_map.unknown_actions().collect::<Vec<_>>(),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn controllers_carry_on_from_where_a_track_stops() {
+        let context = pollster::block_on(RenderContext::headless(
+            64,
+            64,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            true,
+        ))
+        .unwrap();
+        let scene = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/sdf_shapes.ron");
+        let mut state =
+            pollster::block_on(State::new(context, Some(PathBuf::from(scene)), false)).unwrap();
+        while state.camera_controller().name() != "orbit" {
+            state.next_camera_controller();
+        }
+
+        let from = state.camera_pose();
+        let mut to = from;
+        to.position += cgmath::Vector3::new(10.0, 2.0, -5.0);
+        let track = CameraTrack::between("away".to_string(), from, to, 1.0);
+        state.scene_description.camera_tracks.push(track);
+        state.play_camera_track("away").unwrap();
+        state.update(std::time::Duration::from_secs(2));
+        let stopped = state.camera.position;
+        assert!((stopped - to.position).magnitude() < 1e-3);
+
+        // Orbiting a little moves the camera a little, rather than back around the old target
+        let orbit = state.camera_controller();
+        orbit.process_action(input::ORBIT, true);
+        orbit.process_mouse(1.0, 0.0);
+        state.camera_controllers[state.active_controller]
+            .update_camera(&mut state.camera, std::time::Duration::from_millis(16));
+        assert!((state.camera.position - stopped).magnitude() < 0.05);
+    }
+
+    #[test]
+    fn default_bindings_dont_clash() {
+        let input_map = create_input_map(BTreeMap::new(), &create_camera_controllers());
+        assert_eq!(input_map.clashing_defaults(), []);
+    }
 }
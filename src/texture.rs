@@ -17,9 +17,26 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        is_normal_map: bool,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, label)
+        Self::from_image(device, queue, &img, label, is_normal_map)
+    }
+
+    /// Creates a 1x1 texture, used in place of material maps that weren't provided
+    pub fn from_colour(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        colour: [u8; 4],
+        label: &str,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba(colour),
+        ));
+        Self::from_image(device, queue, &img, label, is_normal_map)
     }
 
     pub fn from_image(
@@ -27,6 +44,7 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: &str,
+        is_normal_map: bool,
     ) -> Result<Self> {
         use image::DynamicImage;
         use wgpu::TextureFormat;
@@ -39,11 +57,21 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
+        // Formats without a matching wgpu format (e.g. RGB from JPEGs) are expanded first
+        let converted = match img {
+            DynamicImage::ImageRgba8(_) | DynamicImage::ImageRgba32F(_) => None,
+            DynamicImage::ImageRgb32F(_) => Some(DynamicImage::ImageRgba32F(img.to_rgba32f())),
+            _ => Some(DynamicImage::ImageRgba8(img.to_rgba8())),
+        };
+        let img = converted.as_ref().unwrap_or(img);
+
         let texture = device.create_texture_with_data(
             &queue,
             &wgpu::TextureDescriptor {
                 dimension: wgpu::TextureDimension::D2,
                 format: match img {
+                    // Normal maps hold vectors rather than colours, so skip the sRGB decode
+                    DynamicImage::ImageRgba8(_) if is_normal_map => Some(TextureFormat::Rgba8Unorm),
                     DynamicImage::ImageRgba8(_) => Some(TextureFormat::Rgba8UnormSrgb),
                     DynamicImage::ImageRgba32F(_) => Some(TextureFormat::Rgba32Float),
                     _ => None,
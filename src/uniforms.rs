@@ -0,0 +1,303 @@
+//! WGSL declarations for the structs shared between Rust and the shaders.
+//!
+//! Each `#[repr(C)]` uniform type is registered in `structs` with the fields the shaders see,
+//! leaving out the manual padding. Shaders get the declarations with `#include "uniforms.wgsl"`,
+//! so there's only one copy of each struct. WGSL still lays the fields out by its own alignment
+//! rules, so the tests compile every shader with naga and check that the offsets and sizes it
+//! computes match the Rust types.
+
+use crate::camera::CameraUniform;
+use crate::light::LightUniform;
+use crate::model::MaterialUniform;
+use crate::sdf::SdfObjectUniform;
+
+/// Name shaders include the generated declarations by
+pub const INCLUDE_NAME: &str = "uniforms.wgsl";
+
+/// Rust types with an equivalent WGSL type
+pub trait WgslType {
+    const WGSL: &'static str;
+}
+
+macro_rules! wgsl_type {
+    ($($ty:ty => $wgsl:literal),* $(,)?) => {
+        $(impl WgslType for $ty {
+            const WGSL: &'static str = $wgsl;
+        })*
+    };
+}
+
+wgsl_type! {
+    f32 => "f32",
+    u32 => "u32",
+    i32 => "i32",
+    [f32; 2] => "vec2<f32>",
+    [f32; 3] => "vec3<f32>",
+    [f32; 4] => "vec4<f32>",
+    [[f32; 3]; 3] => "mat3x3<f32>",
+    [[f32; 4]; 4] => "mat4x4<f32>",
+}
+
+#[derive(Debug)]
+pub struct WgslField {
+    pub name: &'static str,
+    pub ty: &'static str,
+    /// Byte offset in the Rust type
+    pub offset: usize,
+}
+
+#[derive(Debug)]
+pub struct WgslStruct {
+    pub name: &'static str,
+    pub rust_name: &'static str,
+    pub fields: Vec<WgslField>,
+    /// Size of the Rust type, including any trailing padding
+    pub size: usize,
+}
+
+impl WgslStruct {
+    pub fn declaration(&self) -> String {
+        let mut declaration = format!(
+            "// Generated from `{}`, {} bytes\nstruct {} {{\n",
+            self.rust_name, self.size, self.name
+        );
+        for field in &self.fields {
+            declaration.push_str(&format!(
+                "    // Offset {}\n    {}: {},\n",
+                field.offset, field.name, field.ty
+            ));
+        }
+        declaration.push_str("}\n");
+        declaration
+    }
+}
+
+/// Takes the field through `get` only so its type can pick the WGSL type
+fn field<S, T: WgslType>(name: &'static str, offset: usize, _get: fn(&S) -> &T) -> WgslField {
+    WgslField {
+        name,
+        ty: T::WGSL,
+        offset,
+    }
+}
+
+/// Registers `$ty` as the WGSL struct `$name` with the listed fields, in order
+macro_rules! wgsl_struct {
+    ($ty:ident as $name:literal { $($field:ident),* $(,)? }) => {
+        WgslStruct {
+            name: $name,
+            rust_name: stringify!($ty),
+            fields: vec![$(
+                field(stringify!($field), std::mem::offset_of!($ty, $field), |s: &$ty| &s.$field),
+            )*],
+            size: std::mem::size_of::<$ty>(),
+        }
+    };
+}
+
+pub fn structs() -> Vec<WgslStruct> {
+    vec![
+        wgsl_struct!(CameraUniform as "CameraUniform" {
+            view_proj, pos, dir, right, up, aspect, projection, view_scale, eye_offset,
+            convergence, lens_offset, focus_distance,
+        }),
+        wgsl_struct!(LightUniform as "Light" {
+            position, colour, strength, radius,
+        }),
+        wgsl_struct!(SdfObjectUniform as "SdfObject" {
+            inverse_transform, params, shape, operation, scale,
+        }),
+        wgsl_struct!(MaterialUniform as "MaterialUniform" {
+            ambient, shininess, diffuse, dissolve, specular, emissive,
+        }),
+    ]
+}
+
+/// Contents of `uniforms.wgsl`
+pub fn wgsl() -> String {
+    structs()
+        .iter()
+        .map(WgslStruct::declaration)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::InstanceRaw;
+    use crate::model::{ModelVertex, Vertex};
+    use crate::shaders;
+
+    fn wgsl_type_name(module: &naga::Module, ty: naga::Handle<naga::Type>) -> String {
+        use naga::{ScalarKind, TypeInner, VectorSize};
+
+        let scalar = |kind, width| match (kind, width) {
+            (ScalarKind::Float, 4) => "f32",
+            (ScalarKind::Uint, 4) => "u32",
+            (ScalarKind::Sint, 4) => "i32",
+            _ => "unknown",
+        };
+        let size = |size| match size {
+            VectorSize::Bi => 2,
+            VectorSize::Tri => 3,
+            VectorSize::Quad => 4,
+        };
+        match module.types[ty].inner {
+            TypeInner::Scalar { kind, width } => scalar(kind, width).to_string(),
+            TypeInner::Vector {
+                size: vector_size,
+                kind,
+                width,
+            } => format!("vec{}<{}>", size(vector_size), scalar(kind, width)),
+            TypeInner::Matrix {
+                columns,
+                rows,
+                width,
+            } => format!(
+                "mat{}x{}<{}>",
+                size(columns),
+                size(rows),
+                scalar(ScalarKind::Float, width)
+            ),
+            ref other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn struct_layouts_match_shaders() {
+        let modules: Vec<_> = shaders::SHADERS
+            .iter()
+            .map(|&shader| (shader.name, shaders::validate(shader).0))
+            .collect();
+
+        for wgsl_struct in structs() {
+            let mut used = false;
+            for (shader, module) in &modules {
+                let (handle, ty) = match module
+                    .types
+                    .iter()
+                    .find(|(_, ty)| ty.name.as_deref() == Some(wgsl_struct.name))
+                {
+                    Some(found) => found,
+                    None => continue,
+                };
+                used = true;
+
+                let (members, span) = match &ty.inner {
+                    naga::TypeInner::Struct { members, span } => (members, *span as usize),
+                    other => panic!(
+                        "{} in {} isn't a struct: {:?}",
+                        wgsl_struct.name, shader, other
+                    ),
+                };
+                let layout: Vec<_> = members
+                    .iter()
+                    .map(|member| {
+                        (
+                            member.name.clone().unwrap_or_default(),
+                            wgsl_type_name(module, member.ty),
+                            member.offset as usize,
+                        )
+                    })
+                    .collect();
+                let expected: Vec<_> = wgsl_struct
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.to_string(), field.ty.to_string(), field.offset))
+                    .collect();
+                assert_eq!(
+                    layout, expected,
+                    "{} in {} is laid out differently to `{}`",
+                    wgsl_struct.name, shader, wgsl_struct.rust_name
+                );
+                assert_eq!(
+                    span, wgsl_struct.size,
+                    "{} in {} has a different size to `{}`",
+                    wgsl_struct.name, shader, wgsl_struct.rust_name
+                );
+
+                // Arrays in storage buffers step by the struct's span, rounded up to its alignment
+                for (_, array) in module.types.iter() {
+                    if let naga::TypeInner::Array { base, stride, .. } = array.inner {
+                        if base == handle {
+                            assert_eq!(
+                                stride as usize, wgsl_struct.size,
+                                "Arrays of {} in {} have a different stride to `{}`",
+                                wgsl_struct.name, shader, wgsl_struct.rust_name
+                            );
+                        }
+                    }
+                }
+            }
+            assert!(used, "No shader uses {}", wgsl_struct.name);
+        }
+    }
+
+    fn vertex_format(module: &naga::Module, ty: naga::Handle<naga::Type>) -> wgpu::VertexFormat {
+        match wgsl_type_name(module, ty).as_str() {
+            "f32" => wgpu::VertexFormat::Float32,
+            "vec2<f32>" => wgpu::VertexFormat::Float32x2,
+            "vec3<f32>" => wgpu::VertexFormat::Float32x3,
+            "vec4<f32>" => wgpu::VertexFormat::Float32x4,
+            other => panic!("No vertex format for {}", other),
+        }
+    }
+
+    #[test]
+    fn vertex_layouts_match_gbuffer_shader() {
+        let (module, _) = shaders::validate(shaders::GBUFFER);
+        let entry_point = module
+            .entry_points
+            .iter()
+            .find(|entry_point| entry_point.stage == naga::ShaderStage::Vertex)
+            .unwrap();
+
+        // Locations the shader reads, from plain arguments and from the members of struct ones
+        let mut inputs = Vec::new();
+        for argument in &entry_point.function.arguments {
+            match &module.types[argument.ty].inner {
+                naga::TypeInner::Struct { members, .. } => {
+                    for member in members {
+                        if let Some(naga::Binding::Location { location, .. }) = member.binding {
+                            inputs.push((location, vertex_format(&module, member.ty)));
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(naga::Binding::Location { location, .. }) = argument.binding {
+                        inputs.push((location, vertex_format(&module, argument.ty)));
+                    }
+                }
+            }
+        }
+        inputs.sort_by_key(|&(location, _)| location);
+
+        let layouts = [ModelVertex::desc(), InstanceRaw::desc()];
+        let mut attributes = Vec::new();
+        for layout in &layouts {
+            let mut ranges: Vec<_> = layout
+                .attributes
+                .iter()
+                .map(|attribute| attribute.offset..attribute.offset + attribute.format.size())
+                .collect();
+            ranges.sort_by_key(|range| range.start);
+            for pair in ranges.windows(2) {
+                assert!(pair[0].end <= pair[1].start, "Vertex attributes overlap");
+            }
+            assert!(
+                ranges.last().unwrap().end <= layout.array_stride,
+                "Vertex attributes run past the end of the vertex"
+            );
+            attributes.extend(
+                layout
+                    .attributes
+                    .iter()
+                    .map(|attribute| (attribute.shader_location, attribute.format)),
+            );
+        }
+        attributes.sort_by_key(|&(location, _)| location);
+
+        assert_eq!(inputs, attributes);
+    }
+}
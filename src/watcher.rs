@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use anyhow::Context;
+use notify::Watcher;
+
+/// Watches a set of files for changes. Their directories are watched rather than the files
+/// themselves, as many editors save by replacing the file, which would end a watch on it.
+pub struct FileWatcher {
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    /// Each file as it was given, along with its canonical path to match events against
+    files: Vec<(PathBuf, PathBuf)>,
+}
+
+impl FileWatcher {
+    pub fn new<P: AsRef<Path>>(files: &[P]) -> anyhow::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver only goes away when the watcher does
+            let _ = sender.send(event);
+        })?;
+
+        let files = files
+            .iter()
+            .map(|file| (file.as_ref().to_path_buf(), canonical(file.as_ref())))
+            .collect::<Vec<_>>();
+        let mut directories = files
+            .iter()
+            .filter_map(|(_, file)| file.parent())
+            .collect::<Vec<_>>();
+        directories.sort();
+        directories.dedup();
+        for directory in directories {
+            watcher
+                .watch(directory, notify::RecursiveMode::NonRecursive)
+                .with_context(|| format!("Couldn't watch {:?}", directory))?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            files,
+        })
+    }
+
+    /// Watched files that have been written to since the last call, each listed once as it was
+    /// originally given
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    eprintln!("File watcher error: {}", e);
+                    continue;
+                }
+            };
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+            for path in event.paths {
+                let path = canonical(&path);
+                for (file, canonical_file) in &self.files {
+                    if *canonical_file == path && !changed.contains(file) {
+                        changed.push(file.clone());
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Paths from events are absolute, so watched paths have to be too for them to compare equal
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}